@@ -10,7 +10,28 @@ M.onPluginLoad = function()
 
     -- while true do end
 
+    ngmp.log.debug("config:", ngmp.config)
+
     print("okay bye !!!")
 end
 
+M.onPluginMessage = function(sender, ...)
+    print("message from " .. sender)
+    return "got it"
+end
+
+M.onPluginUnload = function()
+    print("see you later")
+end
+
+ngmp.events.on("onPlayerJoin", function(steam_id, name)
+    print(name .. " joined the server")
+end)
+
+-- `caller` is the player running the command from chat, or nil for the console
+ngmp.commands.register("hello", "Says hello back", function(caller, ...)
+    local name = caller and caller.name or "console"
+    return ngmp.config.greeting .. " " .. name .. " " .. table.concat({...}, " ")
+end, { usage = "[words...]" })
+
 return M
@@ -0,0 +1,113 @@
+use std::sync::atomic::AtomicUsize;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, oneshot, RwLock};
+use warp::http::StatusCode;
+use warp::Filter;
+
+use crate::config::Config;
+use crate::console::ServerCommand;
+use crate::http;
+
+#[derive(Debug, Deserialize)]
+struct CommandRequest {
+    command: String,
+}
+
+#[derive(Debug, Serialize)]
+struct CommandResponse {
+    output: String,
+}
+
+/// Runs the admin HTTP API. Every `/command` request is a console command that gets executed
+/// by the server loop, with the output sent back as the response. Commands are limited to
+/// what the configured role has permission to run.
+/// `/status` is the same as on the public HTTP server, and needs no token.
+pub async fn admin_api_main(
+    config: Config,
+    cmd_tx: mpsc::Sender<ServerCommand>,
+    current_map: Arc<RwLock<String>>,
+    player_count: Arc<AtomicUsize>,
+) {
+    let general = config.general;
+    let config = config.admin;
+    if config.token.is_empty() {
+        error!("Admin API is enabled but no token is set, refusing to start it!");
+        return;
+    }
+
+    let port = config.port;
+    let expected_auth = format!("Bearer {}", config.token);
+    let role = config.role;
+
+    let command_route = warp::path("command")
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(warp::header::optional::<String>("authorization"))
+        .and(warp::body::content_length_limit(16 * 1024))
+        .and(warp::body::json())
+        .then(move |auth: Option<String>, req: CommandRequest| {
+            let authorized = auth
+                .as_deref()
+                .is_some_and(|auth| constant_time_eq(auth.as_bytes(), expected_auth.as_bytes()));
+            let cmd_tx = cmd_tx.clone();
+            let role = role.clone();
+            async move {
+                if !authorized {
+                    return warp::reply::with_status(
+                        warp::reply::json(&CommandResponse {
+                            output: String::from("Unauthorized"),
+                        }),
+                        StatusCode::UNAUTHORIZED,
+                    );
+                }
+                match run_command(req.command, role, cmd_tx).await {
+                    Some(output) => warp::reply::with_status(
+                        warp::reply::json(&CommandResponse { output }),
+                        StatusCode::OK,
+                    ),
+                    None => warp::reply::with_status(
+                        warp::reply::json(&CommandResponse {
+                            output: String::from("Server is not running"),
+                        }),
+                        StatusCode::SERVICE_UNAVAILABLE,
+                    ),
+                }
+            }
+        });
+
+    let status_route = http::status_route(general, current_map, player_count);
+
+    info!("Admin API listening on port {}", port);
+    warp::serve(command_route.or(status_route))
+        .run(([0, 0, 0, 0], port))
+        .await;
+}
+
+/// Compares two byte strings in time that only depends on their lengths,
+/// so the token can't be guessed one byte at a time.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+async fn run_command(
+    line: String,
+    role: String,
+    cmd_tx: mpsc::Sender<ServerCommand>,
+) -> Option<String> {
+    info!("Admin API command: {}", line);
+    let (reply_tx, reply_rx) = oneshot::channel();
+    cmd_tx
+        .send(ServerCommand {
+            line,
+            reply: Some(reply_tx),
+            role: Some(role),
+        })
+        .await
+        .ok()?;
+    reply_rx.await.ok()
+}
@@ -0,0 +1,369 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use serde::{Deserialize, Serialize};
+
+/// Seconds since the Unix epoch.
+pub fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Parses durations like `30m`, `12h`, `7d` or `2w`. A plain number is taken as minutes.
+/// Zero is refused, as a ban that ends right away does nothing.
+pub fn parse_duration(s: &str) -> Option<Duration> {
+    let (amount, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(index) => s.split_at(index),
+        None => (s, "m"),
+    };
+    let amount = amount.parse::<u64>().ok().filter(|amount| *amount > 0)?;
+    let seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return None,
+    };
+    Some(Duration::from_secs(amount.checked_mul(seconds)?))
+}
+
+/// Formats a number of seconds as e.g. `2d 3h`, leaving out the smaller units.
+pub fn format_duration(seconds: u64) -> String {
+    let units = [
+        ("w", 7 * 24 * 60 * 60),
+        ("d", 24 * 60 * 60),
+        ("h", 60 * 60),
+        ("m", 60),
+    ];
+    let mut parts = Vec::new();
+    let mut rest = seconds;
+    for (unit, size) in units {
+        if rest >= size {
+            parts.push(format!("{}{}", rest / size, unit));
+            rest %= size;
+        }
+        if parts.len() == 2 {
+            break;
+        }
+    }
+    if parts.is_empty() {
+        parts.push(format!("{}s", rest));
+    }
+    parts.join(" ")
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct Ban {
+    pub reason: String,
+    /// Unix time the ban was issued at
+    pub created: u64,
+    /// Unix time the ban runs out, or None if it is permanent
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires: Option<u64>,
+}
+
+impl Ban {
+    /// A ban issued now, permanent if `duration` is None.
+    pub fn new(reason: String, duration: Option<Duration>) -> Self {
+        let created = unix_time();
+        Self {
+            reason,
+            created,
+            expires: duration.map(|d| created.saturating_add(d.as_secs())),
+        }
+    }
+
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires.is_some_and(|expires| expires <= now)
+    }
+
+    /// Describes the ban to the player it applies to.
+    pub fn kick_message(&self) -> String {
+        let mut message = if self.reason.is_empty() {
+            String::from("You are banned from this server")
+        } else {
+            format!("You are banned from this server: {}", self.reason)
+        };
+        match self.expires {
+            Some(expires) => message.push_str(&format!(
+                " (expires in {})",
+                format_duration(expires.saturating_sub(unix_time()))
+            )),
+            None => message.push_str(" (permanent)"),
+        }
+        message
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct Whitelist {
+    /// Only whitelisted players may join while this is enabled
+    pub enabled: bool,
+    /// Steam IDs. TOML keys and values are kept as strings, like in the permissions file
+    pub players: BTreeSet<String>,
+}
+
+/// Banned players and the whitelist, as stored in the bans file.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct BanList {
+    pub whitelist: Whitelist,
+    /// Steam ID -> ban
+    pub bans: BTreeMap<String, Ban>,
+    /// IP address -> ban. Connections from these are dropped before the handshake
+    pub ip_bans: BTreeMap<String, Ban>,
+}
+
+impl BanList {
+    /// Loads the bans file, creating an empty one if it doesn't exist yet.
+    /// A broken file is an error rather than an empty ban list, as that would let every
+    /// banned player back in.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        match std::fs::read_to_string(path) {
+            Ok(content) => {
+                let mut bans = toml::from_str::<BanList>(&content)?;
+                bans.remove_expired();
+                Ok(bans)
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let bans = Self::default();
+                if let Err(e) = bans.save(path) {
+                    error!("Failed to save {}: {}", path.display(), e);
+                }
+                Ok(bans)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Writes the bans file, replacing it atomically.
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let content = toml::to_string_pretty(self)?;
+        let tmp_path = PathBuf::from(format!("{}.tmp", path.display()));
+        std::fs::write(&tmp_path, content)?;
+        std::fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+
+    /// Returns the ban of a player, if they are banned and it hasn't run out yet.
+    pub fn active_ban(&self, steam_id: u64) -> Option<&Ban> {
+        let now = unix_time();
+        self.bans
+            .get(&steam_id.to_string())
+            .filter(|ban| !ban.is_expired(now))
+    }
+
+    /// Returns the ban of an IP address, if it is banned and it hasn't run out yet.
+    pub fn active_ip_ban(&self, ip: IpAddr) -> Option<&Ban> {
+        let now = unix_time();
+        self.ip_bans
+            .get(&ip.to_string())
+            .filter(|ban| !ban.is_expired(now))
+    }
+
+    /// Returns the reason connections from an IP address are refused, if any.
+    pub fn check_ip(&self, ip: IpAddr) -> Result<(), String> {
+        match self.active_ip_ban(ip) {
+            Some(ban) => Err(ban.kick_message()),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns the reason a player can't join the server, if any.
+    pub fn check(&self, steam_id: u64) -> Result<(), String> {
+        if let Some(ban) = self.active_ban(steam_id) {
+            return Err(ban.kick_message());
+        }
+        if self.whitelist.enabled && !self.is_whitelisted(steam_id) {
+            return Err(String::from("You are not whitelisted on this server"));
+        }
+        Ok(())
+    }
+
+    /// Bans a player, replacing any ban they already had.
+    pub fn ban(&mut self, steam_id: u64, reason: String, duration: Option<Duration>) {
+        self.bans
+            .insert(steam_id.to_string(), Ban::new(reason, duration));
+    }
+
+    /// Bans an IP address, replacing any ban it already had.
+    pub fn ban_ip(&mut self, ip: IpAddr, reason: String, duration: Option<Duration>) {
+        self.ip_bans
+            .insert(ip.to_string(), Ban::new(reason, duration));
+    }
+
+    /// Returns false if the IP address wasn't banned.
+    pub fn unban_ip(&mut self, ip: IpAddr) -> bool {
+        self.ip_bans.remove(&ip.to_string()).is_some()
+    }
+
+    /// Returns false if the player wasn't banned.
+    pub fn unban(&mut self, steam_id: u64) -> bool {
+        self.bans.remove(&steam_id.to_string()).is_some()
+    }
+
+    /// Drops bans that ran out. Returns true if there were any.
+    pub fn remove_expired(&mut self) -> bool {
+        let now = unix_time();
+        let len = self.bans.len() + self.ip_bans.len();
+        self.bans.retain(|_, ban| !ban.is_expired(now));
+        self.ip_bans.retain(|_, ban| !ban.is_expired(now));
+        self.bans.len() + self.ip_bans.len() != len
+    }
+
+    pub fn is_whitelisted(&self, steam_id: u64) -> bool {
+        self.whitelist.players.contains(&steam_id.to_string())
+    }
+
+    /// Returns false if nothing changed.
+    pub fn set_whitelisted(&mut self, steam_id: u64, whitelisted: bool) -> bool {
+        if whitelisted {
+            self.whitelist.players.insert(steam_id.to_string())
+        } else {
+            self.whitelist.players.remove(&steam_id.to_string())
+        }
+    }
+}
+
+/// A change to the ban list, requested from the console or by a plugin.
+#[derive(Debug, Clone)]
+pub enum BanChange {
+    /// Permanent if `duration` is None
+    Ban {
+        steam_id: u64,
+        reason: String,
+        duration: Option<Duration>,
+    },
+    Unban(u64),
+    /// Permanent if `duration` is None
+    BanIp {
+        ip: IpAddr,
+        reason: String,
+        duration: Option<Duration>,
+    },
+    UnbanIp(IpAddr),
+    SetWhitelisted {
+        steam_id: u64,
+        whitelisted: bool,
+    },
+    EnableWhitelist(bool),
+}
+
+impl BanChange {
+    /// Returns false if nothing changed.
+    pub fn apply(&self, bans: &mut BanList) -> bool {
+        match self {
+            Self::Ban {
+                steam_id,
+                reason,
+                duration,
+            } => {
+                bans.ban(*steam_id, reason.clone(), *duration);
+                true
+            }
+            Self::Unban(steam_id) => bans.unban(*steam_id),
+            Self::BanIp {
+                ip,
+                reason,
+                duration,
+            } => {
+                bans.ban_ip(*ip, reason.clone(), *duration);
+                true
+            }
+            Self::UnbanIp(ip) => bans.unban_ip(*ip),
+            Self::SetWhitelisted {
+                steam_id,
+                whitelisted,
+            } => bans.set_whitelisted(*steam_id, *whitelisted),
+            Self::EnableWhitelist(enabled) => {
+                std::mem::replace(&mut bans.whitelist.enabled, *enabled) != *enabled
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_durations_with_units() {
+        assert_eq!(parse_duration("45s"), Some(Duration::from_secs(45)));
+        assert_eq!(parse_duration("30m"), Some(Duration::from_secs(30 * 60)));
+        assert_eq!(
+            parse_duration("12h"),
+            Some(Duration::from_secs(12 * 60 * 60))
+        );
+        assert_eq!(
+            parse_duration("7d"),
+            Some(Duration::from_secs(7 * 24 * 60 * 60))
+        );
+        assert_eq!(
+            parse_duration("2w"),
+            Some(Duration::from_secs(14 * 24 * 60 * 60))
+        );
+    }
+
+    #[test]
+    fn plain_numbers_are_minutes() {
+        assert_eq!(parse_duration("15"), Some(Duration::from_secs(15 * 60)));
+    }
+
+    #[test]
+    fn rejects_invalid_durations() {
+        for s in [
+            "",
+            "m",
+            "10x",
+            "1.5h",
+            "-5m",
+            "10 m",
+            "h10",
+            "99999999999999999999w",
+            "0",
+            "0m",
+            "00d",
+        ] {
+            assert_eq!(parse_duration(s), None, "{} was accepted", s);
+        }
+        // Overflows when converted to seconds
+        assert_eq!(parse_duration(&format!("{}w", u64::MAX / 2)), None);
+    }
+
+    #[test]
+    fn formats_the_two_largest_units() {
+        assert_eq!(format_duration(0), "0s");
+        assert_eq!(format_duration(59), "59s");
+        assert_eq!(format_duration(60), "1m");
+        assert_eq!(format_duration(90), "1m");
+        assert_eq!(format_duration(2 * 60 * 60 + 5 * 60 + 7), "2h 5m");
+        assert_eq!(format_duration(3 * 24 * 60 * 60 + 60), "3d 1m");
+        assert_eq!(
+            format_duration(8 * 24 * 60 * 60 + 3 * 60 * 60 + 60),
+            "1w 1d"
+        );
+    }
+
+    #[test]
+    fn formatting_round_trips_single_units() {
+        for s in ["30m", "12h", "6d", "2w"] {
+            let seconds = parse_duration(s).unwrap().as_secs();
+            assert_eq!(format_duration(seconds), s);
+        }
+    }
+
+    #[test]
+    fn a_broken_file_fails_to_load() {
+        let path = std::env::temp_dir().join(format!("ngmp-bans-{}.toml", std::process::id()));
+        std::fs::write(&path, "[bans.\"76561198000000000\"\nreason = ").unwrap();
+        assert!(BanList::load(&path).is_err());
+        let _ = std::fs::remove_file(&path);
+    }
+}
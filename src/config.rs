@@ -18,11 +18,39 @@ pub struct Config {
     pub general: ConfigGeneral,
     #[serde(rename = "Networking")]
     pub networking: ConfigNetworking,
+    #[serde(rename = "Admin", default)]
+    pub admin: ConfigAdmin,
+    #[serde(rename = "MapRotation", default)]
+    pub map_rotation: ConfigMapRotation,
+    #[serde(rename = "Chat", default)]
+    pub chat: ConfigChat,
+    #[serde(rename = "Plugins", default)]
+    pub plugins: ConfigPlugins,
+    #[serde(rename = "Permissions", default)]
+    pub permissions: ConfigPermissions,
+    #[serde(rename = "Bans", default)]
+    pub bans: ConfigBans,
+    #[serde(rename = "Connections", default)]
+    pub connections: ConfigConnections,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ConfigGeneral {
     pub map: String,
+    /// Seconds a client gets to load a map before being kicked
+    #[serde(default = "default_map_load_timeout")]
+    pub map_load_timeout: u64,
+    /// Players that can be online at the same time. 0 means there is no limit
+    #[serde(default)]
+    pub max_players: usize,
+    /// Slots out of `max_players` that only whitelisted players and players with the
+    /// `slots.reserved` permission can take
+    #[serde(default)]
+    pub reserved_slots: usize,
+}
+
+fn default_map_load_timeout() -> u64 {
+    120
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -31,3 +59,189 @@ pub struct ConfigNetworking {
     pub udp_port: u16,
     pub http_port: u16,
 }
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ConfigAdmin {
+    pub enabled: bool,
+    pub port: u16,
+    /// Must be sent as `Authorization: Bearer <token>` with every request
+    pub token: String,
+    /// Role whose permissions the token grants. Built-in commands need `command.<name>`,
+    /// plugin commands the permission they were registered with
+    #[serde(default = "default_admin_role")]
+    pub role: String,
+}
+
+fn default_admin_role() -> String {
+    String::from("admin")
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ConfigMapRotation {
+    pub enabled: bool,
+    /// Seconds between map changes
+    pub interval: u64,
+    /// Seconds before a map change at which the next map is announced in chat
+    pub announce_before: u64,
+    /// Lets players vote on the next map
+    pub voting: bool,
+    pub maps: Vec<String>,
+}
+
+impl Default for ConfigMapRotation {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval: 1800,
+            announce_before: 60,
+            voting: true,
+            maps: Vec::new(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ConfigChat {
+    /// Messages longer than this get cut off
+    pub max_length: usize,
+    /// A client may send at most `rate_limit_messages` every `rate_limit_seconds`.
+    /// 0 disables the limit
+    pub rate_limit_messages: usize,
+    pub rate_limit_seconds: u64,
+    /// Words that get replaced with asterisks
+    pub filtered_words: Vec<String>,
+}
+
+impl Default for ConfigChat {
+    fn default() -> Self {
+        Self {
+            max_length: 256,
+            rate_limit_messages: 5,
+            rate_limit_seconds: 10,
+            filtered_words: Vec::new(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ConfigPlugins {
+    /// Directory that is scanned for plugins at startup
+    pub directory: String,
+    /// Directory with operator overrides for plugin configs, named `<plugin name>.toml`
+    pub config_directory: String,
+    /// Reload plugins automatically when their files change
+    pub hot_reload: bool,
+    /// Restricts plugins to a safe subset of the Lua standard library, with file access
+    /// limited to their own data directory
+    pub sandbox: bool,
+    /// Plugins that are not sandboxed, even if `sandbox` is enabled.
+    /// Only these can change permissions, bans and the whitelist while sandboxing is on
+    pub trusted: Vec<String>,
+    /// Lua calls running longer than this are aborted. Keep it well below the 20ms server
+    /// tick. 0 disables the watchdog, which also keeps the LuaJIT compiler enabled
+    pub watchdog_budget_ms: u64,
+    /// Plugins are disabled after exceeding their time budget this many times. 0 never disables them
+    pub watchdog_max_offenses: u32,
+    /// Plugins are disabled after raising `error_limit` errors within `error_limit_seconds`.
+    /// 0 never disables them
+    pub error_limit: u32,
+    pub error_limit_seconds: u64,
+    /// Hosts plugins may send HTTP requests to. `*.example.com` also allows subdomains
+    pub http_allowed_hosts: Vec<String>,
+    /// HTTP requests taking longer than this are aborted
+    pub http_timeout_ms: u64,
+    /// A plugin may send at most `http_rate_limit_requests` every `http_rate_limit_seconds`.
+    /// 0 disables the limit
+    pub http_rate_limit_requests: usize,
+    pub http_rate_limit_seconds: u64,
+}
+
+impl Default for ConfigPlugins {
+    fn default() -> Self {
+        Self {
+            directory: String::from("plugins"),
+            config_directory: String::from("plugin_config"),
+            hot_reload: false,
+            sandbox: true,
+            trusted: Vec::new(),
+            watchdog_budget_ms: 10,
+            watchdog_max_offenses: 3,
+            error_limit: 50,
+            error_limit_seconds: 60,
+            http_allowed_hosts: Vec::new(),
+            http_timeout_ms: 10000,
+            http_rate_limit_requests: 30,
+            http_rate_limit_seconds: 60,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ConfigPermissions {
+    /// File with all roles and the roles and permissions of every player
+    pub file: String,
+}
+
+impl Default for ConfigPermissions {
+    fn default() -> Self {
+        Self {
+            file: String::from("permissions.toml"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ConfigBans {
+    /// File with all banned players and the whitelist
+    pub file: String,
+}
+
+impl Default for ConfigBans {
+    fn default() -> Self {
+        Self {
+            file: String::from("bans.toml"),
+        }
+    }
+}
+
+/// Limits on incoming connections, which are checked before the handshake starts.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ConfigConnections {
+    /// Connections one IP address may have open at the same time, connecting or connected.
+    /// 0 disables the limit
+    pub max_per_ip: usize,
+    /// An IP address may connect at most `rate_limit_connections` every `rate_limit_seconds`.
+    /// 0 disables the limit
+    pub rate_limit_connections: usize,
+    pub rate_limit_seconds: u64,
+    /// Seconds a client gets to get through the handshake, on top of `map_load_timeout`
+    /// for loading the map. Connections that take longer are dropped
+    #[serde(default = "default_handshake_timeout")]
+    pub handshake_timeout: u64,
+}
+
+fn default_handshake_timeout() -> u64 {
+    30
+}
+
+impl Default for ConfigConnections {
+    fn default() -> Self {
+        Self {
+            max_per_ip: 4,
+            rate_limit_connections: 5,
+            rate_limit_seconds: 30,
+            handshake_timeout: default_handshake_timeout(),
+        }
+    }
+}
+
+impl Default for ConfigAdmin {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            port: 42633,
+            token: String::new(),
+            role: default_admin_role(),
+        }
+    }
+}
@@ -0,0 +1,154 @@
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::config::ConfigConnections;
+
+#[derive(Default)]
+struct IpState {
+    /// When recent connections from this address came in
+    recent: VecDeque<Instant>,
+    /// Connections from this address that are still open, connecting or connected
+    open: usize,
+}
+
+/// Limits how often and how many times a single IP address may connect, so nobody can keep
+/// the accept thread (and the login API behind it) busy.
+pub struct ConnectionLimiter {
+    config: ConfigConnections,
+    state: Arc<Mutex<HashMap<IpAddr, IpState>>>,
+}
+
+/// Counts as one open connection for its IP address until it is dropped.
+/// It lives as long as the connection, which includes the `Client` once it is accepted.
+pub struct ConnectionSlot {
+    ip: IpAddr,
+    state: Arc<Mutex<HashMap<IpAddr, IpState>>>,
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(ip_state) = state.get_mut(&self.ip) {
+            ip_state.open = ip_state.open.saturating_sub(1);
+        }
+    }
+}
+
+impl ConnectionLimiter {
+    pub fn new(config: ConfigConnections) -> Self {
+        Self {
+            config,
+            state: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Registers a new connection from `ip`.
+    /// Returns why it should be refused if the address is over one of its limits.
+    pub fn try_acquire(&self, ip: IpAddr) -> Result<ConnectionSlot, String> {
+        let now = Instant::now();
+        let window = Duration::from_secs(self.config.rate_limit_seconds);
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+
+        // Forget about addresses that have been quiet for a while
+        state.retain(|_, ip_state| {
+            while ip_state
+                .recent
+                .front()
+                .is_some_and(|at| now.duration_since(*at) > window)
+            {
+                ip_state.recent.pop_front();
+            }
+            ip_state.open > 0 || !ip_state.recent.is_empty()
+        });
+
+        let ip_state = state.entry(ip).or_default();
+        if self.config.rate_limit_connections > 0
+            && ip_state.recent.len() >= self.config.rate_limit_connections
+        {
+            return Err(String::from("Too many connection attempts"));
+        }
+        // Attempts count towards the rate limit, even if they are refused below
+        ip_state.recent.push_back(now);
+        if self.config.max_per_ip > 0 && ip_state.open >= self.config.max_per_ip {
+            return Err(String::from("Too many connections"));
+        }
+        ip_state.open += 1;
+
+        Ok(ConnectionSlot {
+            ip,
+            state: self.state.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(
+        max_per_ip: usize,
+        rate_limit_connections: usize,
+        rate_limit_seconds: u64,
+    ) -> ConnectionLimiter {
+        ConnectionLimiter::new(ConfigConnections {
+            max_per_ip,
+            rate_limit_connections,
+            rate_limit_seconds,
+            ..Default::default()
+        })
+    }
+
+    fn ip(last: u8) -> IpAddr {
+        IpAddr::from([10, 0, 0, last])
+    }
+
+    #[test]
+    fn limits_connection_attempts_per_window() {
+        let limiter = limiter(0, 2, 60);
+        let _first = limiter.try_acquire(ip(1)).unwrap();
+        let _second = limiter.try_acquire(ip(1)).unwrap();
+        assert!(limiter.try_acquire(ip(1)).is_err());
+        // Other addresses have their own limit
+        assert!(limiter.try_acquire(ip(2)).is_ok());
+    }
+
+    #[test]
+    fn attempts_expire_after_the_window() {
+        let limiter = limiter(0, 1, 0);
+        drop(limiter.try_acquire(ip(1)).unwrap());
+        std::thread::sleep(Duration::from_millis(10));
+        assert!(limiter.try_acquire(ip(1)).is_ok());
+    }
+
+    #[test]
+    fn dropping_a_slot_frees_it() {
+        let limiter = limiter(1, 0, 60);
+        let slot = limiter.try_acquire(ip(1)).unwrap();
+        assert!(limiter.try_acquire(ip(1)).is_err());
+        drop(slot);
+        assert!(limiter.try_acquire(ip(1)).is_ok());
+    }
+
+    #[test]
+    fn refused_connections_count_towards_the_rate_limit() {
+        let limiter = limiter(1, 3, 60);
+        let _slot = limiter.try_acquire(ip(1)).unwrap();
+        assert!(limiter.try_acquire(ip(1)).is_err());
+        assert!(limiter.try_acquire(ip(1)).is_err());
+        assert_eq!(
+            limiter.try_acquire(ip(1)).err().as_deref(),
+            Some("Too many connection attempts")
+        );
+    }
+
+    #[test]
+    fn zero_disables_the_limits() {
+        let limiter = limiter(0, 0, 60);
+        let slots = (0..100)
+            .map(|_| limiter.try_acquire(ip(1)).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(slots.len(), 100);
+    }
+}
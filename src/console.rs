@@ -0,0 +1,48 @@
+use std::io::BufRead;
+
+use tokio::sync::{mpsc, oneshot};
+
+/// A command line sent to the server loop, either typed into the console or received
+/// from another source. If `reply` is set, the command output is sent back through it
+/// instead of being logged.
+pub struct ServerCommand {
+    pub line: String,
+    pub reply: Option<oneshot::Sender<String>>,
+    /// The command only runs if this role has permission to. None for the console,
+    /// which can run everything
+    pub role: Option<String>,
+}
+
+impl ServerCommand {
+    pub fn new(line: String) -> Self {
+        Self {
+            line,
+            reply: None,
+            role: None,
+        }
+    }
+}
+
+/// Reads commands from stdin and forwards them to the server loop.
+pub fn console_thread(tx: mpsc::Sender<ServerCommand>) {
+    info!("Console thread launched! Type `help` for a list of commands.");
+
+    for line in std::io::stdin().lock().lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => {
+                error!("Failed to read from stdin: {}", e);
+                break;
+            }
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        if tx.blocking_send(ServerCommand::new(line)).is_err() {
+            // Server loop is gone, nothing left to do
+            break;
+        }
+    }
+
+    debug!("Console thread stopped.");
+}
@@ -1,4 +1,11 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
 use serde::{Serialize, Deserialize};
+use tokio::sync::RwLock;
+use warp::Filter;
+
+use crate::config::{Config, ConfigGeneral};
 
 // const LOGIN_API: &'static str = "http://login.ngmp.net:11281";
 const LOGIN_API: &'static str = "http://138.201.33.234:11281";
@@ -26,3 +33,54 @@ pub async fn auth_token_get_steam_info(auth_token: &str) -> anyhow::Result<UserA
         .json()
         .await?)
 }
+
+#[derive(Debug, Serialize)]
+struct StatusResponse {
+    players: usize,
+    /// 0 means there is no limit
+    max_players: usize,
+    reserved_slots: usize,
+    map: String,
+}
+
+/// `GET /status`, reports how full the server is
+pub fn status_route(
+    general: ConfigGeneral,
+    current_map: Arc<RwLock<String>>,
+    player_count: Arc<AtomicUsize>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path("status")
+        .and(warp::path::end())
+        .and(warp::get())
+        .then(move || {
+            let current_map = current_map.clone();
+            let player_count = player_count.clone();
+            let general = general.clone();
+            async move {
+                warp::reply::json(&StatusResponse {
+                    players: player_count.load(Ordering::Relaxed),
+                    max_players: general.max_players,
+                    reserved_slots: general.reserved_slots,
+                    map: current_map.read().await.clone(),
+                })
+            }
+        })
+}
+
+/// Runs the public HTTP server on the port clients are told about, so players can see
+/// whether there is room before joining.
+pub async fn http_main(
+    config: Config,
+    current_map: Arc<RwLock<String>>,
+    player_count: Arc<AtomicUsize>,
+) {
+    let port = config.networking.http_port;
+    let routes = status_route(config.general, current_map, player_count);
+    match warp::serve(routes).try_bind_ephemeral(([0, 0, 0, 0], port)) {
+        Ok((_, server)) => {
+            info!("HTTP server listening on port {}", port);
+            server.await;
+        }
+        Err(e) => error!("Failed to start HTTP server on port {}: {}", port, e),
+    }
+}
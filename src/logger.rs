@@ -1,4 +1,5 @@
 use log::{Level, LevelFilter, Metadata, Record, SetLoggerError};
+use std::io::Write;
 use std::sync::atomic::{AtomicUsize, Ordering};
 
 pub fn init(level_filter: LevelFilter, enable_colors: bool) -> Result<(), SetLoggerError> {
@@ -60,5 +61,7 @@ impl log::Log for NGMPLogger {
         }
     }
 
-    fn flush(&self) {}
+    fn flush(&self) {
+        let _ = std::io::stdout().flush();
+    }
 }
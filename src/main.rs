@@ -1,46 +1,87 @@
 #[macro_use]
 extern crate log;
 
-use tokio::sync::mpsc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::{mpsc, oneshot, RwLock};
 
 use ngmp_protocol_impl::server_launcher::Packet;
 use ngmp_protocol_impl::{connection::*, server_launcher};
 
+mod admin;
+mod bans;
 mod config;
+mod connection_limits;
+mod console;
 mod data;
 mod http;
 mod logger;
+mod permissions;
 mod plugin;
 mod server;
 
+use bans::BanList;
 use config::Config;
+use connection_limits::{ConnectionLimiter, ConnectionSlot};
+use console::ServerCommand;
+use permissions::Permissions;
 use server::*;
 
-fn client_accept_thread(config: Config, tx: mpsc::Sender<Client>) {
+fn client_accept_thread(
+    config: Config,
+    current_map: Arc<RwLock<String>>,
+    bans: Arc<RwLock<BanList>>,
+    permissions: Arc<RwLock<Permissions>>,
+    player_count: Arc<AtomicUsize>,
+    tx: mpsc::Sender<Client>,
+    shutdown_rx: oneshot::Receiver<()>,
+) {
     info!("Client accept thread launched!");
 
-    let tx2 = tx.clone();
     let rt = tokio::runtime::Runtime::new().expect("Failed to spawn client accept runtime!");
-    let handle = rt.spawn(async move {
-        client_accept_async(config, tx).await;
+    rt.block_on(async move {
+        tokio::select!(
+            _ = client_accept_async(config, current_map, bans, permissions, player_count, tx) => {},
+            _ = shutdown_rx => {},
+        );
     });
 
-    loop {
-        if tx2.is_closed() {
-            break;
+    info!("Client accept thread stopped.");
+}
+
+/// Resolves once the process receives SIGINT (Ctrl+C) or SIGTERM.
+async fn wait_for_shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut sigterm = signal(SignalKind::terminate()).expect("Failed to listen for SIGTERM!");
+        tokio::select!(
+            _ = tokio::signal::ctrl_c() => {},
+            _ = sigterm.recv() => {},
+        );
+    }
+    #[cfg(not(unix))]
+    {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            error!("Failed to listen for Ctrl+C: {}", e);
+            std::future::pending::<()>().await;
         }
-        std::thread::sleep(std::time::Duration::from_secs(1));
     }
-
-    handle.abort();
-    info!("Client accept thread killed.");
 }
 
 /// Handles the accepting of a client
 async fn accept_client(
     mut tcp_conn: TcpConnection<Packet>,
     addr: std::net::SocketAddr,
+    connection_slot: ConnectionSlot,
     config: &Config,
+    current_map: &RwLock<String>,
+    bans: &RwLock<BanList>,
+    permissions: &RwLock<Permissions>,
+    player_count: &AtomicUsize,
 ) -> Option<Client> {
     // Handle client version
     let packet = tcp_conn
@@ -82,6 +123,37 @@ async fn accept_client(
     };
     let user_info = match http::auth_token_get_steam_info(&auth_data.auth_code).await {
         Ok(user_info) => {
+            // Banned and non-whitelisted players, and players that wouldn't fit, are turned away
+            // before they load the map. A reconnecting player still counts while their old
+            // connection is open, the server checks for a free slot again once they are in
+            let steam_id = user_info.steam_id;
+            let mut check = bans.read().await.check(steam_id);
+            if check.is_ok() {
+                let reserved = bans.read().await.is_whitelisted(steam_id)
+                    || permissions
+                        .read()
+                        .await
+                        .has_permission(steam_id, RESERVED_SLOT_PERMISSION);
+                let online = player_count.load(Ordering::Relaxed);
+                if !slot_available(&config.general, online, reserved) {
+                    check = Err(String::from("Server is full"));
+                }
+            }
+            if let Err(reason) = check {
+                info!(
+                    "Refusing {} ({}): {}",
+                    user_info.user.name, steam_id, reason
+                );
+                tcp_conn
+                    .write_packet(&Packet::PlayerKick(
+                        server_launcher::generic::PlayerKickPacket { reason },
+                    ))
+                    .await
+                    .map_err(|e| error!("Client error: {}", e))
+                    .ok()?;
+                return None;
+            }
+
             // Confirm auth data
             tcp_conn
                 .write_packet(&Packet::Confirmation(
@@ -128,12 +200,13 @@ async fn accept_client(
     debug!("UDP addr: {}", udp_addr);
 
     // LoadMap packet
-    let confirm_id = 8; // TODO: Generate one randomly :);
+    let confirm_id = server::next_confirm_id();
+    let map_name = current_map.read().await.clone();
     tcp_conn
         .write_packet(&Packet::LoadMap(
             server_launcher::serverinfo::LoadMapPacket {
                 confirm_id,
-                map_name: config.general.map.clone(),
+                map_name: map_name.clone(),
             },
         ))
         .await
@@ -154,6 +227,8 @@ async fn accept_client(
                     udp_addr,
                     user_info.steam_id,
                     user_info.user,
+                    map_name,
+                    connection_slot,
                 ))
             } else {
                 error!("Invalid confirmation ID");
@@ -167,22 +242,76 @@ async fn accept_client(
     }
 }
 
-async fn client_accept_async(config: Config, tx: mpsc::Sender<Client>) {
+async fn client_accept_async(
+    config: Config,
+    current_map: Arc<RwLock<String>>,
+    bans: Arc<RwLock<BanList>>,
+    permissions: Arc<RwLock<Permissions>>,
+    player_count: Arc<AtomicUsize>,
+    tx: mpsc::Sender<Client>,
+) {
     let tcp_addr = format!("0.0.0.0:{}", config.networking.tcp_port);
     let tcp_listener = tokio::net::TcpListener::bind(&tcp_addr)
         .await
         .expect("Failed to bind TCP socket!");
 
+    let limiter = ConnectionLimiter::new(config.connections.clone());
+    let handshake_timeout =
+        Duration::from_secs(config.connections.handshake_timeout + config.general.map_load_timeout);
+    let config = Arc::new(config);
+
     loop {
         match tcp_listener.accept().await {
             Ok((socket, addr)) => {
+                // These are checked before anything else, so refused connections cost next to nothing.
+                // Dropping the socket closes the connection
+                let connection_slot = match limiter.try_acquire(addr.ip()) {
+                    Ok(slot) => slot,
+                    Err(reason) => {
+                        debug!("Refusing connection from {}: {}", addr, reason);
+                        continue;
+                    }
+                };
+                if let Err(reason) = bans.read().await.check_ip(addr.ip()) {
+                    debug!("Refusing connection from {}: {}", addr, reason);
+                    continue;
+                }
+
                 info!("New connection incoming from {}", addr);
                 let tcp_conn = TcpConnection::<Packet>::from_stream(socket);
-                if let Some(client) = accept_client(tcp_conn, addr, &config).await {
-                    if let Err(e) = tx.send(client).await {
-                        error!("Failed to send client over to server thread: {}", e);
+                let config = config.clone();
+                let current_map = current_map.clone();
+                let bans = bans.clone();
+                let permissions = permissions.clone();
+                let player_count = player_count.clone();
+                let tx = tx.clone();
+                // Every handshake runs on its own, so a slow client doesn't hold up everyone else,
+                // and a client that stops responding doesn't keep its connection slot forever
+                tokio::spawn(async move {
+                    let res = tokio::time::timeout(
+                        handshake_timeout,
+                        accept_client(
+                            tcp_conn,
+                            addr,
+                            connection_slot,
+                            &config,
+                            &current_map,
+                            &bans,
+                            &permissions,
+                            &player_count,
+                        ),
+                    )
+                    .await;
+                    match res {
+                        Ok(Some(client)) => {
+                            if let Err(e) = tx.send(client).await {
+                                error!("Failed to send client over to server thread: {}", e);
+                            }
+                        }
+                        Ok(None) => {}
+                        Err(_) => info!("Handshake with {} timed out", addr),
                     }
-                }
+                });
             }
             Err(e) => error!("Error accepting client: {}", e),
         }
@@ -202,13 +331,96 @@ async fn main() {
         .map_err(|e| error!("{}", e))
         .unwrap();
 
+    let current_map = Arc::new(RwLock::new(config.general.map.clone()));
+    let bans_path = std::path::Path::new(&config.bans.file);
+    // Running without the ban list would let every banned player back in, and without the
+    // permissions file the server would forget everyone's roles. Both files are left as they
+    // are for the operator to fix.
+    let bans = match BanList::load(bans_path) {
+        Ok(bans) => Arc::new(RwLock::new(bans)),
+        Err(e) => {
+            error!("Failed to load {}: {}", bans_path.display(), e);
+            std::process::exit(1);
+        }
+    };
+    let permissions_path = std::path::Path::new(&config.permissions.file);
+    let permissions = match Permissions::load(permissions_path) {
+        Ok(permissions) => permissions,
+        Err(e) => {
+            error!("Failed to load {}: {}", permissions_path.display(), e);
+            std::process::exit(1);
+        }
+    };
+    let shared_permissions = Arc::new(RwLock::new(permissions.clone()));
+    let player_count = Arc::new(AtomicUsize::new(0));
+
     // We use a bounded channel to avoid the server using unreasonable
     // amounts of RAM if something goes wrong
     let (tx, rx) = mpsc::channel(250);
-    {
+    let (accept_shutdown_tx, accept_shutdown_rx) = oneshot::channel();
+    let accept_thread = {
         let config_ref = config.clone();
-        std::thread::spawn(move || client_accept_thread(config_ref, tx));
+        let current_map = current_map.clone();
+        let bans = bans.clone();
+        let permissions = shared_permissions.clone();
+        let player_count = player_count.clone();
+        std::thread::spawn(move || {
+            client_accept_thread(
+                config_ref,
+                current_map,
+                bans,
+                permissions,
+                player_count,
+                tx,
+                accept_shutdown_rx,
+            )
+        })
+    };
+
+    tokio::spawn(http::http_main(
+        config.clone(),
+        current_map.clone(),
+        player_count.clone(),
+    ));
+
+    let (cmd_tx, cmd_rx) = mpsc::channel(32);
+    if config.admin.enabled {
+        tokio::spawn(admin::admin_api_main(
+            config.clone(),
+            cmd_tx.clone(),
+            current_map.clone(),
+            player_count.clone(),
+        ));
+    }
+    {
+        let cmd_tx = cmd_tx.clone();
+        tokio::spawn(async move {
+            wait_for_shutdown_signal().await;
+            info!("Shutdown signal received.");
+            let _ = cmd_tx.send(ServerCommand::new(String::from("stop"))).await;
+        });
+    }
+    std::thread::spawn(move || console::console_thread(cmd_tx));
+
+    server::server_main(
+        rx,
+        cmd_rx,
+        udp_listener,
+        config,
+        current_map,
+        bans,
+        permissions,
+        shared_permissions,
+        player_count,
+    )
+    .await;
+
+    // The receiving end is gone at this point, so the accept thread may have already exited
+    let _ = accept_shutdown_tx.send(());
+    if accept_thread.join().is_err() {
+        error!("Client accept thread panicked!");
     }
 
-    server::server_main(rx, udp_listener).await;
+    info!("Server stopped.");
+    log::logger().flush();
 }
@@ -0,0 +1,362 @@
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+/// Every player has this role, whether it is listed for them or not
+pub const DEFAULT_ROLE: &str = "player";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct Role {
+    /// Roles whose permissions this role gets as well
+    pub inherits: Vec<String>,
+    pub permissions: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct PlayerPermissions {
+    pub roles: Vec<String>,
+    /// Permissions granted to this player directly, on top of those of their roles
+    pub permissions: Vec<String>,
+}
+
+impl PlayerPermissions {
+    fn is_empty(&self) -> bool {
+        self.roles.is_empty() && self.permissions.is_empty()
+    }
+}
+
+/// Roles and the permissions of every player, as stored in the permissions file.
+///
+/// Permissions are dotted nodes like `command.kick`. A permission ending in `.*` grants
+/// every node below it, and `*` grants everything.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct Permissions {
+    pub roles: BTreeMap<String, Role>,
+    /// Steam ID -> permissions. TOML keys are always strings
+    pub players: BTreeMap<String, PlayerPermissions>,
+}
+
+fn builtin_roles() -> [(&'static str, Role); 3] {
+    let role = |inherits: &[&str], permissions: &[&str]| Role {
+        inherits: inherits.iter().map(|s| s.to_string()).collect(),
+        permissions: permissions.iter().map(|s| s.to_string()).collect(),
+    };
+    [
+        (
+            DEFAULT_ROLE,
+            role(&[], &["chat.send", "chat.msg", "map.vote"]),
+        ),
+        (
+            "moderator",
+            role(
+                &[DEFAULT_ROLE],
+                &["command.kick", "command.say", "command.list"],
+            ),
+        ),
+        ("admin", role(&[], &["*"])),
+    ]
+}
+
+/// Returns true if `permission` grants `node`.
+fn grants(permission: &str, node: &str) -> bool {
+    match permission.strip_suffix('*') {
+        Some("") => true,
+        Some(prefix) if prefix.ends_with('.') => node.starts_with(prefix),
+        _ => permission == node,
+    }
+}
+
+impl Permissions {
+    /// Loads the permissions file, creating it with the built-in roles if it doesn't exist yet.
+    /// A broken file is an error, and left alone so it can be fixed by hand.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let mut permissions = match std::fs::read_to_string(path) {
+            Ok(content) => toml::from_str::<Permissions>(&content)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Self::default(),
+            Err(e) => return Err(e.into()),
+        };
+
+        if permissions.add_builtin_roles() {
+            if let Err(e) = permissions.save(path) {
+                error!("Failed to save {}: {}", path.display(), e);
+            }
+        }
+        Ok(permissions)
+    }
+
+    /// Adds the built-in roles that are missing. Returns true if any were added.
+    fn add_builtin_roles(&mut self) -> bool {
+        let mut added = false;
+        for (name, role) in builtin_roles() {
+            if !self.roles.contains_key(name) {
+                self.roles.insert(name.to_string(), role);
+                added = true;
+            }
+        }
+        added
+    }
+
+    /// Writes the permissions file, replacing it atomically.
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let content = toml::to_string_pretty(self)?;
+        let tmp_path = PathBuf::from(format!("{}.tmp", path.display()));
+        std::fs::write(&tmp_path, content)?;
+        std::fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+
+    /// Returns every role a player has, including the ones inherited through other roles.
+    pub fn roles_of(&self, steam_id: u64) -> Vec<String> {
+        let mut roles = vec![DEFAULT_ROLE.to_string()];
+        if let Some(player) = self.players.get(&steam_id.to_string()) {
+            roles.extend(player.roles.iter().cloned());
+        }
+        self.with_inherited_roles(roles)
+    }
+
+    /// Adds every role inherited by `pending`, directly or through other roles.
+    fn with_inherited_roles(&self, mut pending: Vec<String>) -> Vec<String> {
+        let mut roles = Vec::new();
+        let mut seen = HashSet::new();
+        while let Some(name) = pending.pop() {
+            if !seen.insert(name.clone()) {
+                continue;
+            }
+            if let Some(role) = self.roles.get(&name) {
+                pending.extend(role.inherits.iter().cloned());
+            }
+            roles.push(name);
+        }
+        roles
+    }
+
+    /// Returns true if a role grants `node`, itself or through the roles it inherits.
+    pub fn role_has_permission(&self, role: &str, node: &str) -> bool {
+        self.with_inherited_roles(vec![role.to_string()])
+            .iter()
+            .filter_map(|name| self.roles.get(name))
+            .flat_map(|role| role.permissions.iter())
+            .any(|permission| grants(permission, node))
+    }
+
+    pub fn has_permission(&self, steam_id: u64, node: &str) -> bool {
+        let direct = self
+            .players
+            .get(&steam_id.to_string())
+            .into_iter()
+            .flat_map(|player| player.permissions.iter());
+        let from_roles = self
+            .roles_of(steam_id)
+            .into_iter()
+            .filter_map(|name| self.roles.get(&name))
+            .flat_map(|role| role.permissions.clone())
+            .collect::<Vec<_>>();
+
+        direct
+            .chain(from_roles.iter())
+            .any(|permission| grants(permission, node))
+    }
+
+    /// Returns false if the role doesn't exist or the player already has it.
+    pub fn add_role(&mut self, steam_id: u64, role: &str) -> bool {
+        if !self.roles.contains_key(role) {
+            return false;
+        }
+        let player = self.players.entry(steam_id.to_string()).or_default();
+        if player.roles.iter().any(|r| r == role) {
+            return false;
+        }
+        player.roles.push(role.to_string());
+        true
+    }
+
+    /// Returns false if the player didn't have the role.
+    pub fn remove_role(&mut self, steam_id: u64, role: &str) -> bool {
+        self.update_player(steam_id, |player| {
+            let len = player.roles.len();
+            player.roles.retain(|r| r != role);
+            player.roles.len() != len
+        })
+    }
+
+    /// Returns false if the player already had the permission.
+    pub fn grant(&mut self, steam_id: u64, permission: &str) -> bool {
+        let player = self.players.entry(steam_id.to_string()).or_default();
+        if player.permissions.iter().any(|p| p == permission) {
+            return false;
+        }
+        player.permissions.push(permission.to_string());
+        true
+    }
+
+    /// Returns false if the player didn't have the permission.
+    /// Permissions coming from roles can't be revoked this way.
+    pub fn revoke(&mut self, steam_id: u64, permission: &str) -> bool {
+        self.update_player(steam_id, |player| {
+            let len = player.permissions.len();
+            player.permissions.retain(|p| p != permission);
+            player.permissions.len() != len
+        })
+    }
+
+    /// Changes an existing player entry, removing it if nothing is left in it.
+    fn update_player(
+        &mut self,
+        steam_id: u64,
+        f: impl FnOnce(&mut PlayerPermissions) -> bool,
+    ) -> bool {
+        let key = steam_id.to_string();
+        let Some(player) = self.players.get_mut(&key) else {
+            return false;
+        };
+        let changed = f(player);
+        if player.is_empty() {
+            self.players.remove(&key);
+        }
+        changed
+    }
+}
+
+/// A change to a player's permissions, requested from the console or by a plugin.
+#[derive(Debug, Clone)]
+pub enum PermissionChange {
+    AddRole(String),
+    RemoveRole(String),
+    Grant(String),
+    Revoke(String),
+}
+
+impl PermissionChange {
+    /// Parses e.g. `addrole admin`, as used by the console.
+    pub fn parse(action: &str, value: &str) -> Option<Self> {
+        let value = value.to_string();
+        match action {
+            "addrole" => Some(Self::AddRole(value)),
+            "removerole" => Some(Self::RemoveRole(value)),
+            "grant" => Some(Self::Grant(value)),
+            "revoke" => Some(Self::Revoke(value)),
+            _ => None,
+        }
+    }
+
+    /// Returns false if nothing changed.
+    pub fn apply(&self, permissions: &mut Permissions, steam_id: u64) -> bool {
+        match self {
+            Self::AddRole(role) => permissions.add_role(steam_id, role),
+            Self::RemoveRole(role) => permissions.remove_role(steam_id, role),
+            Self::Grant(permission) => permissions.grant(steam_id, permission),
+            Self::Revoke(permission) => permissions.revoke(steam_id, permission),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exact_permissions_only_grant_themselves() {
+        assert!(grants("command.kick", "command.kick"));
+        assert!(!grants("command.kick", "command.kickall"));
+        assert!(!grants("command.kick", "command"));
+        assert!(!grants("command", "command.kick"));
+    }
+
+    #[test]
+    fn wildcards_grant_everything_below_them() {
+        assert!(grants("*", "command.kick"));
+        assert!(grants("*", "slots.reserved"));
+        assert!(grants("command.*", "command.kick"));
+        assert!(grants("command.*", "command.plugin.reload"));
+        assert!(!grants("command.*", "command"));
+        assert!(!grants("command.*", "commands.kick"));
+        assert!(!grants("chat.*", "command.kick"));
+        // Only whole nodes can be wildcards
+        assert!(!grants("comm*", "command.kick"));
+    }
+
+    fn with_roles(roles: &[(&str, &[&str], &[&str])]) -> Permissions {
+        let mut permissions = Permissions::default();
+        for (name, inherits, granted) in roles {
+            permissions.roles.insert(
+                name.to_string(),
+                Role {
+                    inherits: inherits.iter().map(|s| s.to_string()).collect(),
+                    permissions: granted.iter().map(|s| s.to_string()).collect(),
+                },
+            );
+        }
+        permissions
+    }
+
+    fn sorted(mut roles: Vec<String>) -> Vec<String> {
+        roles.sort();
+        roles
+    }
+
+    #[test]
+    fn everyone_has_the_default_role() {
+        let permissions = with_roles(&[(DEFAULT_ROLE, &[], &["chat.send"])]);
+        assert_eq!(permissions.roles_of(1), vec![DEFAULT_ROLE]);
+        assert!(permissions.has_permission(1, "chat.send"));
+        assert!(!permissions.has_permission(1, "command.kick"));
+    }
+
+    #[test]
+    fn roles_are_inherited_transitively() {
+        let mut permissions = with_roles(&[
+            (DEFAULT_ROLE, &[], &["chat.send"]),
+            ("moderator", &[DEFAULT_ROLE], &["command.kick"]),
+            ("admin", &["moderator"], &["command.*"]),
+        ]);
+        assert!(permissions.add_role(1, "admin"));
+        assert_eq!(
+            sorted(permissions.roles_of(1)),
+            vec!["admin", "moderator", DEFAULT_ROLE]
+        );
+        assert!(permissions.has_permission(1, "chat.send"));
+        assert!(permissions.has_permission(1, "command.stop"));
+        assert!(permissions.role_has_permission("admin", "chat.send"));
+        assert!(!permissions.role_has_permission("moderator", "command.stop"));
+    }
+
+    #[test]
+    fn inheritance_cycles_terminate() {
+        let mut permissions = with_roles(&[
+            ("a", &["b"], &["node.a"]),
+            ("b", &["c"], &["node.b"]),
+            ("c", &["a", "c"], &["node.c"]),
+        ]);
+        assert!(permissions.add_role(1, "b"));
+        assert_eq!(
+            sorted(permissions.roles_of(1)),
+            vec!["a", "b", "c", DEFAULT_ROLE]
+        );
+        assert!(permissions.has_permission(1, "node.a"));
+        assert!(permissions.role_has_permission("c", "node.b"));
+    }
+
+    #[test]
+    fn unknown_roles_grant_nothing() {
+        let permissions = with_roles(&[("admin", &["missing"], &["*"])]);
+        assert!(!permissions.role_has_permission("missing", "command.kick"));
+        assert!(permissions.role_has_permission("admin", "command.kick"));
+    }
+
+    #[test]
+    fn a_broken_file_fails_to_load_and_is_left_alone() {
+        let path = std::env::temp_dir().join(format!("ngmp-perms-{}.toml", std::process::id()));
+        std::fs::write(&path, "this is not [valid toml").unwrap();
+
+        assert!(Permissions::load(&path).is_err());
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "this is not [valid toml"
+        );
+        let _ = std::fs::remove_file(&path);
+    }
+}
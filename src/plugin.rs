@@ -1,30 +1,195 @@
 use mlua::prelude::*;
 use thiserror::Error;
 
-use std::collections::HashSet;
+use std::collections::{HashMap, VecDeque};
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime};
 
 use std::sync::Arc;
 use tokio::sync::Mutex;
 
+use crate::bans::{BanChange, BanList};
+use crate::config::ConfigPlugins;
+use crate::permissions::{PermissionChange, Permissions};
+use commands::LuaCommand;
+use errors::PluginCaller;
+use events::EventHandler;
+use http::HttpState;
+use storage::PluginStorage;
+use timers::Timer;
+use watchdog::Watchdog;
+
+mod api;
+mod bans;
+mod commands;
+mod config;
+mod discovery;
+mod errors;
+mod events;
+mod http;
+mod logging;
+mod permissions;
+mod players;
+mod sandbox;
+mod storage;
+mod timers;
+mod vehicles;
+mod watchdog;
+
+pub use commands::CommandInfo;
+pub use discovery::{
+    discover_plugin, discover_plugins, last_modified, resolve_load_order, DiscoveredPlugin,
+};
+pub use players::PlayerInfo;
+pub use vehicles::VehicleInfo;
+
 #[derive(Debug, Error)]
 pub enum PluginError {
     #[error("lua error: {0}")]
     LuaError(LuaError),
 
+    /// Raised by the plugin's own code. These are already logged with a traceback
+    #[error("plugin errored: {0}")]
+    PluginErrored(LuaError),
+
     #[error("failed to load plugin: {0}")]
     FailedToLoadPlugin(std::io::Error),
+
+    #[error("failed to load plugin config: {0}")]
+    FailedToLoadConfig(anyhow::Error),
+
+    #[error("plugin `{0}` is already loaded")]
+    AlreadyLoaded(String),
+
+    #[error("plugin `{0}` is not loaded")]
+    NotLoaded(String),
+}
+
+impl PluginError {
+    /// Whether the error was already logged when it happened, so it shouldn't be logged again.
+    pub fn is_reported(&self) -> bool {
+        matches!(self, Self::PluginErrored(_))
+    }
+}
+
+/// Everything that is read from disk to load a plugin. It is read and checked up front,
+/// so a broken edit doesn't take down the version of the plugin that is already running.
+struct PluginSource {
+    info: DiscoveredPlugin,
+    code: String,
+    config: toml::Table,
+    /// Newest modification time of the plugin files when they were read
+    modified: Option<SystemTime>,
+}
+
+pub struct LoadedPlugin {
+    pub info: DiscoveredPlugin,
+    /// Newest modification time of the plugin files when it was loaded
+    modified: Option<SystemTime>,
+    /// The code and config it was loaded with, to bring it back if a reload fails
+    code: String,
+    config: toml::Table,
+    /// The table returned by the plugin's `main.lua`
+    table: LuaTable,
+    /// How often the watchdog had to abort this plugin
+    watchdog_offenses: u32,
+    /// How many errors the plugin raised since it was loaded
+    error_count: u32,
+    /// When the plugin raised its most recent errors, to decide when to disable it
+    recent_errors: VecDeque<Instant>,
+}
+
+/// A loaded plugin, as shown to server operators.
+pub struct PluginSummary {
+    pub name: String,
+    pub version: String,
+    pub errors: u32,
+}
+
+/// Something a plugin asked the server to do. These are queued up and carried out by the
+/// server after the Lua call returns, so plugins never touch server state directly.
+pub enum ApiAction {
+    ChangeMap(String),
+    VoteMap {
+        steam_id: u64,
+        map: String,
+    },
+    KickPlayer {
+        steam_id: u64,
+        reason: String,
+    },
+    /// Sends a chat message from the server to one player, or everyone if `steam_id` is None
+    SendMessage {
+        steam_id: Option<u64>,
+        message: String,
+    },
+    DeleteVehicle {
+        steam_id: u64,
+        vehicle_id: u16,
+    },
+    /// Moves a vehicle, keeping its rotation if `rot` is None
+    TeleportVehicle {
+        steam_id: u64,
+        vehicle_id: u16,
+        pos: [f32; 3],
+        rot: Option<[f32; 4]>,
+    },
+    ChangePermissions {
+        steam_id: u64,
+        change: PermissionChange,
+    },
+    ChangeBans(BanChange),
+    /// Not requested by a plugin, but queued up when a plugin keeps misbehaving
+    DisablePlugin(String),
 }
 
 pub struct LuaNgmpApi {
-    loaded_plugins: HashSet<String>,
+    /// Loaded plugins, in load order
+    loaded_plugins: Vec<LoadedPlugin>,
+    commands: HashMap<String, LuaCommand>,
+    /// Event name -> handlers registered through `ngmp.events.on`
+    event_handlers: HashMap<String, Vec<EventHandler>>,
+    /// Plugin name -> storage
+    storages: HashMap<String, PluginStorage>,
+    /// Timer ID -> timer
+    timers: HashMap<u64, Timer>,
+    next_timer_id: u64,
+    http: HttpState,
+
+    current_map: String,
+    players: HashMap<u64, PlayerInfo>,
+    /// (owner, vehicle ID) -> vehicle
+    vehicles: HashMap<(u64, u16), VehicleInfo>,
+    permissions: Permissions,
+    bans: BanList,
+    actions: Vec<ApiAction>,
 }
 
 impl LuaNgmpApi {
     pub fn new() -> Self {
         Self {
-            loaded_plugins: HashSet::new(),
+            loaded_plugins: Vec::new(),
+            commands: HashMap::new(),
+            event_handlers: HashMap::new(),
+            storages: HashMap::new(),
+            timers: HashMap::new(),
+            next_timer_id: 0,
+            http: HttpState::new(),
+
+            current_map: String::new(),
+            players: HashMap::new(),
+            vehicles: HashMap::new(),
+            permissions: Permissions::default(),
+            bans: BanList::default(),
+            actions: Vec::new(),
         }
     }
+
+    fn find_plugin(&self, plugin_name: &str) -> Option<&LoadedPlugin> {
+        self.loaded_plugins
+            .iter()
+            .find(|p| p.info.manifest.name == plugin_name)
+    }
 }
 
 /// The Lua environment houses the global Lua state and manages (re)loading plugins and calling functions.
@@ -32,15 +197,30 @@ pub struct LuaEnvironment {
     lua: Lua,
 
     ngmp_api: Arc<Mutex<LuaNgmpApi>>,
-    loaded_plugins: HashSet<String>,
+    config: ConfigPlugins,
+    caller: PluginCaller,
 }
 
 impl LuaEnvironment {
-    pub fn new() -> LuaResult<Self> {
+    pub fn new(config: ConfigPlugins) -> LuaResult<Self> {
+        let lua = Lua::new();
+        let watchdog = if config.watchdog_budget_ms > 0 {
+            Some(Watchdog::install(
+                &lua,
+                Duration::from_millis(config.watchdog_budget_ms),
+            )?)
+        } else {
+            None
+        };
+
+        let ngmp_api = Arc::new(Mutex::new(LuaNgmpApi::new()));
+        let caller = PluginCaller::new(&lua, ngmp_api.clone(), config.clone(), watchdog)?;
+
         let mut s = Self {
-            lua: Lua::new(),
-            ngmp_api: Arc::new(Mutex::new(LuaNgmpApi::new())),
-            loaded_plugins: HashSet::new(),
+            lua,
+            ngmp_api,
+            config,
+            caller,
         };
 
         s.init_lua_env()?;
@@ -49,101 +229,323 @@ impl LuaEnvironment {
     }
 
     fn init_lua_env(&mut self) -> LuaResult<()> {
-        // Define the `ngmp` api
-        let ngmp_api_table = self.lua.create_table()?;
-
-        let get_plugins_fn = {
-            let api_ref = self.ngmp_api.clone();
-            self.lua.create_async_function(move |_lua: Lua, _: ()| {
-                let api = api_ref.clone();
-                async move {
-                    let lock = api.lock().await;
-                    let plugins_vec = lock
-                        .loaded_plugins
-                        .iter()
-                        .map(|s| s.clone())
-                        .collect::<Vec<String>>();
-                    Ok(plugins_vec)
-                }
-            })?
-        };
-        ngmp_api_table.set("get_plugins", get_plugins_fn)?;
-
-        // Define our custom print function
-        let print_fn = self.lua.create_function(|_lua, (msg,): (String,)| {
-            info!("[LUA] {}", msg);
-            Ok(())
-        })?;
+        // Plugins get their own print, this one is only used by code running outside of them
+        let print_fn = self.create_print_fn("lua")?;
 
-        // Set up the globals
+        // Set up the globals. These are only the base every plugin environment is copied from,
+        // plugins never see this table itself.
         let globals = self.lua.globals();
-        globals.set("ngmp", ngmp_api_table)?;
         globals.set("print", print_fn)?;
 
         Ok(())
     }
 
-    pub async fn load_plugin<P: AsRef<std::path::Path>>(
-        &mut self,
-        plugin_name: String,
-        path: P,
-    ) -> Result<(), PluginError> {
-        let path = path.as_ref();
+    /// Creates the environment (global table) for a single plugin, with its own `ngmp` api.
+    /// Library tables are copied too, so a plugin changing e.g. `string` doesn't affect others.
+    fn create_plugin_env(
+        &self,
+        plugin: &DiscoveredPlugin,
+        config: &toml::Table,
+    ) -> LuaResult<LuaTable> {
+        let plugin_name = &plugin.manifest.name;
+        let trusted = self.is_trusted(plugin_name);
+        let env = self.lua.create_table()?;
+        if trusted {
+            for pair in self.lua.globals().pairs::<LuaValue, LuaValue>() {
+                let (key, value) = pair?;
+                if let LuaValue::String(name) = &key {
+                    if name.to_string_lossy() == "_G" {
+                        continue;
+                    }
+                }
+                let value = match value {
+                    LuaValue::Table(table) => LuaValue::Table(self.shallow_copy(&table)?),
+                    value => value,
+                };
+                env.raw_set(key, value)?;
+            }
+        } else {
+            self.populate_sandboxed_env(&env)?;
+        }
+        env.raw_set("_G", env.clone())?;
+        env.raw_set("print", self.create_print_fn(plugin_name)?)?;
 
-        let lua_code =
-            std::fs::read_to_string(path).map_err(|e| PluginError::FailedToLoadPlugin(e))?;
+        let api_table = self.create_api_table(plugin_name)?;
+        api_table.set("config", self.lua.to_value(config)?)?;
+        api_table.set("log", self.create_log_table(plugin_name)?)?;
+        api_table.set("commands", self.create_commands_table(plugin_name)?)?;
+        api_table.set("events", self.create_events_table(plugin_name)?)?;
+        api_table.set("storage", self.create_storage_table(plugin_name)?)?;
+        api_table.set("http", self.create_http_table(plugin_name)?)?;
+        api_table.set("timer", self.create_timer_table(plugin_name)?)?;
+        api_table.set("fs", self.create_fs_table(plugin.data_dir())?)?;
+        api_table.set("players", self.create_players_table()?)?;
+        api_table.set("vehicles", self.create_vehicles_table()?)?;
+        api_table.set("permissions", self.create_permissions_table(trusted)?)?;
+        api_table.set("bans", self.create_bans_table(trusted)?)?;
+        api_table.set("whitelist", self.create_whitelist_table(trusted)?)?;
+        env.raw_set("ngmp", api_table)?;
+        Ok(env)
+    }
 
-        let plugin: LuaTable = self
-            .lua
-            .load(lua_code)
-            .set_name(&plugin_name)
-            .eval()
-            .map_err(|e| PluginError::LuaError(e))?;
+    fn shallow_copy(&self, table: &LuaTable) -> LuaResult<LuaTable> {
+        let copy = self.lua.create_table()?;
+        for pair in table.pairs::<LuaValue, LuaValue>() {
+            let (key, value) = pair?;
+            copy.raw_set(key, value)?;
+        }
+        Ok(copy)
+    }
+
+    /// Loads a plugin and calls its `onPluginLoad`. If anything goes wrong along the way,
+    /// everything the plugin registered so far is rolled back.
+    pub async fn load_plugin(&mut self, plugin: DiscoveredPlugin) -> Result<(), PluginError> {
+        let source = self.read_plugin(plugin)?;
+        self.load_source(source).await
+    }
 
+    /// Reads a plugin's code and config, and checks the code for syntax errors.
+    fn read_plugin(&self, plugin: DiscoveredPlugin) -> Result<PluginSource, PluginError> {
+        let modified = last_modified(&plugin.dir);
+        let code = std::fs::read_to_string(plugin.entry_path())
+            .map_err(|e| PluginError::FailedToLoadPlugin(e))?;
+        let config = config::load_plugin_config(&plugin, Path::new(&self.config.config_directory))
+            .map_err(|e| PluginError::FailedToLoadConfig(e))?;
         self.lua
-            .globals()
-            .set(plugin_name.as_str(), plugin)
+            .load(code.as_str())
+            .set_name(&plugin.manifest.name)
+            .into_function()
+            .map_err(|e| PluginError::LuaError(e))?;
+
+        Ok(PluginSource {
+            info: plugin,
+            code,
+            config,
+            modified,
+        })
+    }
+
+    async fn load_source(&mut self, source: PluginSource) -> Result<(), PluginError> {
+        let plugin_name = source.info.manifest.name.clone();
+        if self.is_loaded(&plugin_name).await {
+            return Err(PluginError::AlreadyLoaded(plugin_name));
+        }
+
+        let res = self.init_plugin(source).await;
+        if res.is_err() {
+            self.remove_plugin(&plugin_name).await;
+            if let Err(e) = self.lua.gc_collect() {
+                error!("{}", e);
+            }
+        }
+        res
+    }
+
+    async fn init_plugin(&mut self, source: PluginSource) -> Result<(), PluginError> {
+        let PluginSource {
+            info: plugin,
+            code,
+            config,
+            modified,
+        } = source;
+        let plugin_name = plugin.manifest.name.clone();
+
+        self.ngmp_api.lock().await.storages.insert(
+            plugin_name.clone(),
+            PluginStorage::open(plugin.storage_path()),
+        );
+
+        let env = self
+            .create_plugin_env(&plugin, &config)
             .map_err(|e| PluginError::LuaError(e))?;
+        let main_fn = self
+            .lua
+            .load(code.as_str())
+            .set_name(&plugin_name)
+            .set_environment(env)
+            .into_function()
+            .map_err(|e| PluginError::LuaError(e))?;
+        let table: LuaTable = self
+            .call_plugin_fn(&plugin_name, "main.lua", &main_fn, ())
+            .await
+            .map_err(|e| PluginError::PluginErrored(e))?;
 
         {
             let mut lock = self.ngmp_api.lock().await;
-            lock.loaded_plugins.insert(plugin_name.clone());
+            lock.loaded_plugins.push(LoadedPlugin {
+                info: plugin,
+                modified,
+                code,
+                config,
+                table,
+                watchdog_offenses: 0,
+                error_count: 0,
+                recent_errors: VecDeque::new(),
+            });
         }
 
-        self.loaded_plugins.insert(plugin_name.clone());
-
         let _: Option<()> = self
             .call_async_fn(&plugin_name, "onPluginLoad", ())
             .await
-            .map_err(|e| PluginError::LuaError(e))?;
+            .map_err(|e| PluginError::PluginErrored(e))?;
 
         Ok(())
     }
 
+    /// Calls `onPluginUnload` and removes everything the plugin registered.
+    /// Returns the plugin info, so it can be loaded again.
+    pub async fn unload_plugin(
+        &mut self,
+        plugin_name: &str,
+    ) -> Result<DiscoveredPlugin, PluginError> {
+        Ok(self.unload(plugin_name).await?.info)
+    }
+
+    async fn unload(&mut self, plugin_name: &str) -> Result<LoadedPlugin, PluginError> {
+        if !self.is_loaded(plugin_name).await {
+            return Err(PluginError::NotLoaded(plugin_name.to_string()));
+        }
+
+        // Errors are already reported, the plugin gets unloaded either way
+        let _: LuaResult<Option<()>> = self.call_async_fn(plugin_name, "onPluginUnload", ()).await;
+
+        let plugin = self
+            .remove_plugin(plugin_name)
+            .await
+            .ok_or_else(|| PluginError::NotLoaded(plugin_name.to_string()))?;
+
+        // Dropping the environment and plugin table gets rid of all its globals.
+        // Get rid of anything that was only referenced by the plugin right away
+        self.lua
+            .gc_collect()
+            .map_err(|e| PluginError::LuaError(e))?;
+
+        Ok(plugin)
+    }
+
+    /// Removes everything a plugin registered through the api, and the plugin itself if it
+    /// made it into the list of loaded plugins.
+    async fn remove_plugin(&self, plugin_name: &str) -> Option<LoadedPlugin> {
+        self.remove_event_handlers(plugin_name).await;
+        self.remove_timers(plugin_name).await;
+        self.close_storage(plugin_name).await;
+        self.cancel_http_requests(plugin_name).await;
+
+        let mut lock = self.ngmp_api.lock().await;
+        lock.commands.retain(|_, cmd| cmd.plugin != plugin_name);
+        let index = lock
+            .loaded_plugins
+            .iter()
+            .position(|p| p.info.manifest.name == plugin_name)?;
+        Some(lock.loaded_plugins.remove(index))
+    }
+
+    /// Loads a plugin again from disk, picking up changes to its manifest too.
+    /// The new version is read and checked before the running one is unloaded, and if it still
+    /// fails to load, the previous version is brought back.
+    pub async fn reload_plugin(&mut self, plugin_name: &str) -> Result<(), PluginError> {
+        let dir = self
+            .ngmp_api
+            .lock()
+            .await
+            .find_plugin(plugin_name)
+            .map(|p| p.info.dir.clone())
+            .ok_or_else(|| PluginError::NotLoaded(plugin_name.to_string()))?;
+        let plugin = discover_plugin(&dir).map_err(|e| {
+            PluginError::FailedToLoadPlugin(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                e.to_string(),
+            ))
+        })?;
+        let source = self.read_plugin(plugin)?;
+
+        let old = self.unload(plugin_name).await?;
+        let Err(e) = self.load_source(source).await else {
+            return Ok(());
+        };
+
+        // Only try the new version again once its files change again
+        let previous = PluginSource {
+            modified: last_modified(&old.info.dir),
+            info: old.info,
+            code: old.code,
+            config: old.config,
+        };
+        match self.load_source(previous).await {
+            Ok(()) => warn!(
+                "Kept the previous version of plugin {} running",
+                plugin_name
+            ),
+            Err(e) if !e.is_reported() => {
+                error!("Failed to restore plugin {}: {}", plugin_name, e)
+            }
+            Err(_) => error!("Failed to restore plugin {}", plugin_name),
+        }
+        Err(e)
+    }
+
+    /// Returns all plugins whose files changed on disk since they were loaded.
+    pub async fn changed_plugins(&self) -> Vec<DiscoveredPlugin> {
+        let lock = self.ngmp_api.lock().await;
+        lock.loaded_plugins
+            .iter()
+            .filter(|p| last_modified(&p.info.dir) != p.modified)
+            .map(|p| p.info.clone())
+            .collect()
+    }
+
+    pub async fn is_loaded(&self, plugin_name: &str) -> bool {
+        self.ngmp_api
+            .lock()
+            .await
+            .find_plugin(plugin_name)
+            .is_some()
+    }
+
+    pub async fn set_current_map(&self, map: String) {
+        self.ngmp_api.lock().await.current_map = map;
+    }
+
+    /// Takes all actions plugins queued up since the last call.
+    pub async fn take_actions(&self) -> Vec<ApiAction> {
+        std::mem::take(&mut self.ngmp_api.lock().await.actions)
+    }
+
+    /// Returns every loaded plugin, in load order.
+    pub async fn loaded_plugins(&self) -> Vec<PluginSummary> {
+        let lock = self.ngmp_api.lock().await;
+        lock.loaded_plugins
+            .iter()
+            .map(|p| PluginSummary {
+                name: p.info.manifest.name.clone(),
+                version: p.info.manifest.version.clone(),
+                errors: p.error_count,
+            })
+            .collect()
+    }
+
     pub async fn call_async_fn<A: IntoLuaMulti, T: FromLuaMulti>(
         &self,
         plugin_name: &str,
         func_name: &str,
         args: A,
     ) -> LuaResult<Option<T>> {
-        let plugin: LuaTable = self.lua.globals().get(plugin_name)?;
+        let plugin = {
+            let lock = self.ngmp_api.lock().await;
+            lock.find_plugin(plugin_name)
+                .ok_or_else(|| {
+                    LuaError::runtime(format!("plugin `{}` is not loaded", plugin_name))
+                })?
+                .table
+                .clone()
+        };
         if let Ok(func) = plugin.get::<LuaFunction>(func_name) {
-            Ok(Some(func.call_async(args).await?))
+            Ok(Some(
+                self.call_plugin_fn(plugin_name, func_name, &func, args)
+                    .await?,
+            ))
         } else {
             Ok(None)
         }
     }
-
-    pub async fn event_on_player_auth(&self, steam_id: u64, name: String) {
-        let sid = steam_id.to_string();
-        for plugin_name in &self.loaded_plugins {
-            let res: LuaResult<Option<()>> = self
-                .call_async_fn(&plugin_name, "onPlayerAuth", (sid.clone(), name.clone()))
-                .await;
-
-            if let Err(e) = res {
-                error!("[LUA] {}", e);
-            }
-        }
-    }
 }
@@ -0,0 +1,153 @@
+use mlua::prelude::*;
+
+use super::players::parse_steam_id;
+use super::{ApiAction, LuaEnvironment};
+
+impl LuaEnvironment {
+    /// Creates the `ngmp` api table for a single plugin.
+    /// Every function in it knows which plugin it belongs to.
+    pub(super) fn create_api_table(&self, plugin_name: &str) -> LuaResult<LuaTable> {
+        let ngmp_api_table = self.lua.create_table()?;
+
+        let get_plugins_fn = {
+            let api_ref = self.ngmp_api.clone();
+            self.lua.create_async_function(move |_lua: Lua, _: ()| {
+                let api = api_ref.clone();
+                async move {
+                    let lock = api.lock().await;
+                    let plugins_vec = lock
+                        .loaded_plugins
+                        .iter()
+                        .map(|p| p.info.manifest.name.clone())
+                        .collect::<Vec<String>>();
+                    Ok(plugins_vec)
+                }
+            })?
+        };
+        ngmp_api_table.set("get_plugins", get_plugins_fn)?;
+
+        let get_map_fn = {
+            let api_ref = self.ngmp_api.clone();
+            self.lua.create_async_function(move |_lua: Lua, _: ()| {
+                let api = api_ref.clone();
+                async move { Ok(api.lock().await.current_map.clone()) }
+            })?
+        };
+        ngmp_api_table.set("get_map", get_map_fn)?;
+
+        let set_map_fn = {
+            let api_ref = self.ngmp_api.clone();
+            self.lua
+                .create_async_function(move |_lua: Lua, (map,): (String,)| {
+                    let api = api_ref.clone();
+                    async move {
+                        api.lock().await.actions.push(ApiAction::ChangeMap(map));
+                        Ok(())
+                    }
+                })?
+        };
+        ngmp_api_table.set("set_map", set_map_fn)?;
+
+        let vote_map_fn = {
+            let api_ref = self.ngmp_api.clone();
+            self.lua.create_async_function(
+                move |_lua: Lua, (steam_id, map): (String, String)| {
+                    let api = api_ref.clone();
+                    async move {
+                        let steam_id = parse_steam_id(&steam_id)?;
+                        api.lock()
+                            .await
+                            .actions
+                            .push(ApiAction::VoteMap { steam_id, map });
+                        Ok(())
+                    }
+                },
+            )?
+        };
+        ngmp_api_table.set("vote_map", vote_map_fn)?;
+
+        // Plugins can talk to each other by sending messages, which end up in the
+        // `onPluginMessage(sender, ...)` function of the receiving plugin.
+        // Errors in the receiver are reported against the receiver, not the sender.
+        let send_message_fn = {
+            let api_ref = self.ngmp_api.clone();
+            let caller = self.caller.clone();
+            let sender = plugin_name.to_string();
+            self.lua.create_async_function(
+                move |lua: Lua, (target, args): (String, LuaMultiValue)| {
+                    let api = api_ref.clone();
+                    let caller = caller.clone();
+                    let sender = sender.clone();
+                    async move {
+                        let handler = {
+                            let lock = api.lock().await;
+                            let plugin = lock.find_plugin(&target).ok_or_else(|| {
+                                LuaError::runtime(format!("plugin `{}` is not loaded", target))
+                            })?;
+                            plugin.table.get::<Option<LuaFunction>>("onPluginMessage")?
+                        };
+                        let Some(func) = handler else {
+                            return Ok(LuaMultiValue::new());
+                        };
+                        let res = caller
+                            .call_plugin_fn::<LuaMultiValue>(
+                                &lua,
+                                &target,
+                                "onPluginMessage",
+                                &func,
+                                (sender, args),
+                            )
+                            .await;
+                        // Already reported, the sender just gets nothing back
+                        Ok(res.unwrap_or_else(|_| LuaMultiValue::new()))
+                    }
+                },
+            )?
+        };
+        ngmp_api_table.set("send_message", send_message_fn)?;
+
+        let broadcast_message_fn = {
+            let api_ref = self.ngmp_api.clone();
+            let caller = self.caller.clone();
+            let sender = plugin_name.to_string();
+            self.lua
+                .create_async_function(move |lua: Lua, args: LuaMultiValue| {
+                    let api = api_ref.clone();
+                    let caller = caller.clone();
+                    let sender = sender.clone();
+                    async move {
+                        let handlers = {
+                            let lock = api.lock().await;
+                            lock.loaded_plugins
+                                .iter()
+                                .filter(|p| p.info.manifest.name != sender)
+                                .filter_map(|p| {
+                                    let func = p
+                                        .table
+                                        .get::<Option<LuaFunction>>("onPluginMessage")
+                                        .ok()??;
+                                    Some((p.info.manifest.name.clone(), func))
+                                })
+                                .collect::<Vec<_>>()
+                        };
+                        for (target, func) in handlers {
+                            // Already reported, a broken receiver shouldn't stop the others
+                            let _: LuaResult<()> = caller
+                                .call_plugin_fn(
+                                    &lua,
+                                    &target,
+                                    "onPluginMessage",
+                                    &func,
+                                    (sender.clone(), args.clone()),
+                                )
+                                .await;
+                        }
+                        Ok(())
+                    }
+                })?
+        };
+        ngmp_api_table.set("broadcast_message", broadcast_message_fn)?;
+
+        Ok(ngmp_api_table)
+    }
+}
@@ -0,0 +1,200 @@
+use std::time::Duration;
+
+use mlua::prelude::*;
+
+use super::players::parse_steam_id;
+use super::{ApiAction, LuaEnvironment};
+use crate::bans::{unix_time, BanChange, BanList};
+
+impl LuaEnvironment {
+    /// Replaces the ban list plugins see. Called by the server whenever it changes.
+    pub async fn set_bans(&self, bans: BanList) {
+        self.ngmp_api.lock().await.bans = bans;
+    }
+
+    /// Creates the `ngmp.bans` table.
+    /// Only trusted plugins can ban and unban players, anyone can look bans up.
+    pub(super) fn create_bans_table(&self, trusted: bool) -> LuaResult<LuaTable> {
+        let bans_table = self.lua.create_table()?;
+
+        // Permanent unless `seconds` is given. Online players are kicked right away
+        let ban_fn = {
+            let api_ref = self.ngmp_api.clone();
+            self.lua.create_async_function(
+                move |_lua: Lua,
+                      (steam_id, reason, seconds): (String, Option<String>, Option<u64>)| {
+                    let api = api_ref.clone();
+                    async move {
+                        let steam_id = parse_steam_id(&steam_id)?;
+                        if seconds == Some(0) {
+                            return Err(LuaError::runtime("a ban must last longer than 0 seconds"));
+                        }
+                        api.lock()
+                            .await
+                            .actions
+                            .push(ApiAction::ChangeBans(BanChange::Ban {
+                                steam_id,
+                                reason: reason.unwrap_or_default(),
+                                duration: seconds.map(Duration::from_secs),
+                            }));
+                        Ok(())
+                    }
+                },
+            )?
+        };
+        bans_table.set("ban", ban_fn)?;
+
+        let unban_fn = {
+            let api_ref = self.ngmp_api.clone();
+            self.lua
+                .create_async_function(move |_lua: Lua, (steam_id,): (String,)| {
+                    let api = api_ref.clone();
+                    async move {
+                        let steam_id = parse_steam_id(&steam_id)?;
+                        api.lock()
+                            .await
+                            .actions
+                            .push(ApiAction::ChangeBans(BanChange::Unban(steam_id)));
+                        Ok(())
+                    }
+                })?
+        };
+        bans_table.set("unban", unban_fn)?;
+
+        // Returns a table with `reason`, `created` and `expires` (nil for permanent bans)
+        // as Unix timestamps, or nil if the player isn't banned
+        let get_fn = {
+            let api_ref = self.ngmp_api.clone();
+            self.lua
+                .create_async_function(move |lua: Lua, (steam_id,): (String,)| {
+                    let api = api_ref.clone();
+                    async move {
+                        let steam_id = parse_steam_id(&steam_id)?;
+                        let ban = api.lock().await.bans.active_ban(steam_id).cloned();
+                        let Some(ban) = ban else {
+                            return Ok(None);
+                        };
+                        let table = lua.create_table()?;
+                        table.set("reason", ban.reason)?;
+                        table.set("created", ban.created)?;
+                        table.set("expires", ban.expires)?;
+                        Ok(Some(table))
+                    }
+                })?
+        };
+        bans_table.set("get", get_fn)?;
+
+        let list_fn = {
+            let api_ref = self.ngmp_api.clone();
+            self.lua.create_async_function(move |_lua: Lua, _: ()| {
+                let api = api_ref.clone();
+                async move {
+                    let now = unix_time();
+                    let lock = api.lock().await;
+                    Ok(lock
+                        .bans
+                        .bans
+                        .iter()
+                        .filter(|(_, ban)| !ban.is_expired(now))
+                        .map(|(steam_id, _)| steam_id.clone())
+                        .collect::<Vec<_>>())
+                }
+            })?
+        };
+        bans_table.set("list", list_fn)?;
+        self.restrict_to_trusted(trusted, &bans_table, "bans", &["ban", "unban"])?;
+
+        Ok(bans_table)
+    }
+
+    /// Creates the `ngmp.whitelist` table.
+    /// Only trusted plugins can change the whitelist, anyone can look it up.
+    pub(super) fn create_whitelist_table(&self, trusted: bool) -> LuaResult<LuaTable> {
+        let whitelist_table = self.lua.create_table()?;
+
+        for (name, whitelisted) in [("add", true), ("remove", false)] {
+            let api_ref = self.ngmp_api.clone();
+            let change_fn =
+                self.lua
+                    .create_async_function(move |_lua: Lua, (steam_id,): (String,)| {
+                        let api = api_ref.clone();
+                        async move {
+                            let steam_id = parse_steam_id(&steam_id)?;
+                            api.lock().await.actions.push(ApiAction::ChangeBans(
+                                BanChange::SetWhitelisted {
+                                    steam_id,
+                                    whitelisted,
+                                },
+                            ));
+                            Ok(())
+                        }
+                    })?;
+            whitelist_table.set(name, change_fn)?;
+        }
+
+        let has_fn = {
+            let api_ref = self.ngmp_api.clone();
+            self.lua
+                .create_async_function(move |_lua: Lua, (steam_id,): (String,)| {
+                    let api = api_ref.clone();
+                    async move {
+                        let steam_id = parse_steam_id(&steam_id)?;
+                        Ok(api.lock().await.bans.is_whitelisted(steam_id))
+                    }
+                })?
+        };
+        whitelist_table.set("has", has_fn)?;
+
+        let list_fn = {
+            let api_ref = self.ngmp_api.clone();
+            self.lua.create_async_function(move |_lua: Lua, _: ()| {
+                let api = api_ref.clone();
+                async move {
+                    let lock = api.lock().await;
+                    Ok(lock
+                        .bans
+                        .whitelist
+                        .players
+                        .iter()
+                        .cloned()
+                        .collect::<Vec<_>>())
+                }
+            })?
+        };
+        whitelist_table.set("list", list_fn)?;
+
+        let is_enabled_fn = {
+            let api_ref = self.ngmp_api.clone();
+            self.lua.create_async_function(move |_lua: Lua, _: ()| {
+                let api = api_ref.clone();
+                async move { Ok(api.lock().await.bans.whitelist.enabled) }
+            })?
+        };
+        whitelist_table.set("is_enabled", is_enabled_fn)?;
+
+        // Enabling the whitelist kicks everyone online that isn't on it
+        let set_enabled_fn = {
+            let api_ref = self.ngmp_api.clone();
+            self.lua
+                .create_async_function(move |_lua: Lua, (enabled,): (bool,)| {
+                    let api = api_ref.clone();
+                    async move {
+                        api.lock()
+                            .await
+                            .actions
+                            .push(ApiAction::ChangeBans(BanChange::EnableWhitelist(enabled)));
+                        Ok(())
+                    }
+                })?
+        };
+        whitelist_table.set("set_enabled", set_enabled_fn)?;
+        self.restrict_to_trusted(
+            trusted,
+            &whitelist_table,
+            "whitelist",
+            &["add", "remove", "set_enabled"],
+        )?;
+
+        Ok(whitelist_table)
+    }
+}
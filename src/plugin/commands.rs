@@ -0,0 +1,219 @@
+use mlua::prelude::*;
+
+use super::LuaEnvironment;
+use crate::server::is_builtin_command;
+
+/// A command registered by a plugin through `ngmp.commands.register`.
+pub struct LuaCommand {
+    pub(super) plugin: String,
+    help: String,
+    usage: String,
+    /// Players need this permission to run the command from chat
+    permission: Option<String>,
+    chat: bool,
+    console: bool,
+    func: LuaFunction,
+}
+
+/// A plugin command, as shown in help listings.
+pub struct CommandInfo {
+    pub name: String,
+    pub plugin: String,
+    pub help: String,
+    pub usage: String,
+    pub permission: Option<String>,
+    pub chat: bool,
+    pub console: bool,
+}
+
+impl CommandInfo {
+    fn new(name: &str, cmd: &LuaCommand) -> Self {
+        Self {
+            name: name.to_string(),
+            plugin: cmd.plugin.clone(),
+            help: cmd.help.clone(),
+            usage: cmd.usage.clone(),
+            permission: cmd.permission.clone(),
+            chat: cmd.chat,
+            console: cmd.console,
+        }
+    }
+}
+
+impl LuaEnvironment {
+    /// Creates the `ngmp.commands` table for a single plugin.
+    pub(super) fn create_commands_table(&self, plugin_name: &str) -> LuaResult<LuaTable> {
+        let commands_table = self.lua.create_table()?;
+
+        // register(name, help, fn, [options]), with options being a table of
+        // `chat` and `console` (both default to true), `permission` and `usage`.
+        // `fn` is called with the player running the command (nil for the console)
+        // followed by the command arguments.
+        // Names of built-in commands and commands of other plugins can't be taken.
+        let register_command_fn = {
+            let api_ref = self.ngmp_api.clone();
+            let plugin = plugin_name.to_string();
+            self.lua.create_async_function(
+                move |_lua: Lua,
+                      (name, help, func, options): (
+                    String,
+                    String,
+                    LuaFunction,
+                    Option<LuaTable>,
+                )| {
+                    let api = api_ref.clone();
+                    let plugin = plugin.clone();
+                    async move {
+                        let option = |key: &str| -> LuaResult<Option<LuaValue>> {
+                            match &options {
+                                Some(options) => options.get::<Option<LuaValue>>(key),
+                                None => Ok(None),
+                            }
+                        };
+                        let chat = !matches!(option("chat")?, Some(LuaValue::Boolean(false)));
+                        let console = !matches!(option("console")?, Some(LuaValue::Boolean(false)));
+                        let permission = match option("permission")? {
+                            Some(LuaValue::String(s)) => Some(s.to_string_lossy()),
+                            _ => None,
+                        };
+                        let usage = match option("usage")? {
+                            Some(LuaValue::String(s)) => s.to_string_lossy(),
+                            _ => String::new(),
+                        };
+
+                        let name = name.trim_start_matches('/').to_string();
+                        // Built-in commands always win, so this one could never be run
+                        if is_builtin_command(&name) {
+                            return Err(LuaError::runtime(format!(
+                                "`{}` is a built-in command",
+                                name
+                            )));
+                        }
+                        let mut lock = api.lock().await;
+                        if let Some(existing) = lock.commands.get(&name) {
+                            if existing.plugin != plugin {
+                                return Err(LuaError::runtime(format!(
+                                    "command `{}` is already registered by plugin `{}`",
+                                    name, existing.plugin
+                                )));
+                            }
+                        }
+                        lock.commands.insert(
+                            name,
+                            LuaCommand {
+                                plugin,
+                                help,
+                                usage,
+                                permission,
+                                chat,
+                                console,
+                                func,
+                            },
+                        );
+                        Ok(())
+                    }
+                },
+            )?
+        };
+        commands_table.set("register", register_command_fn)?;
+
+        Ok(commands_table)
+    }
+
+    /// Returns every command registered by plugins, sorted by plugin and name.
+    pub async fn command_list(&self) -> Vec<CommandInfo> {
+        let lock = self.ngmp_api.lock().await;
+        let mut commands = lock
+            .commands
+            .iter()
+            .map(|(name, cmd)| CommandInfo::new(name, cmd))
+            .collect::<Vec<_>>();
+        commands.sort_by(|a, b| (&a.plugin, &a.name).cmp(&(&b.plugin, &b.name)));
+        commands
+    }
+
+    pub async fn find_command(&self, name: &str) -> Option<CommandInfo> {
+        let lock = self.ngmp_api.lock().await;
+        lock.commands
+            .get(name)
+            .map(|cmd| CommandInfo::new(name, cmd))
+    }
+
+    /// Runs a plugin command with the given arguments. `caller` is the Steam ID and name of the
+    /// player running it from chat, or None for the console. Permissions are up to the caller.
+    /// Returns None if no plugin registered a command with this name.
+    pub async fn call_command(
+        &self,
+        name: &str,
+        caller: Option<(u64, &str)>,
+        args: Vec<String>,
+    ) -> Option<LuaResult<Option<String>>> {
+        let (plugin, func) = {
+            let lock = self.ngmp_api.lock().await;
+            let cmd = lock.commands.get(name)?;
+            (cmd.plugin.clone(), cmd.func.clone())
+        };
+
+        let caller = match caller {
+            Some((steam_id, player_name)) => {
+                let res = self.lua.create_table().and_then(|table| {
+                    table.set("steam_id", steam_id.to_string())?;
+                    table.set("name", player_name)?;
+                    Ok(LuaValue::Table(table))
+                });
+                match res {
+                    Ok(caller) => caller,
+                    Err(e) => return Some(Err(e)),
+                }
+            }
+            None => LuaValue::Nil,
+        };
+        let args = args.into_iter().collect::<LuaVariadic<String>>();
+        Some(
+            self.call_plugin_fn(
+                &plugin,
+                &format!("command `{}`", name),
+                &func,
+                (caller, args),
+            )
+            .await,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ConfigPlugins;
+
+    async fn register(env: &LuaEnvironment, plugin: &str, name: &str) -> LuaResult<()> {
+        let register_fn: LuaFunction = env.create_commands_table(plugin)?.get("register")?;
+        let func = env.lua.create_function(|_, ()| Ok(()))?;
+        register_fn.call_async((name, "", func)).await
+    }
+
+    #[tokio::test]
+    async fn plugins_can_not_take_each_others_commands() {
+        let env = LuaEnvironment::new(ConfigPlugins::default()).unwrap();
+        register(&env, "a", "hello").await.unwrap();
+        assert!(register(&env, "b", "hello").await.is_err());
+        assert!(register(&env, "b", "/hello").await.is_err());
+        assert_eq!(env.find_command("hello").await.unwrap().plugin, "a");
+
+        // A plugin may register its own command again
+        register(&env, "a", "hello").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn built_in_commands_can_not_be_registered() {
+        let env = LuaEnvironment::new(ConfigPlugins::default()).unwrap();
+        for name in ["kick", "/stop", "help", "perms"] {
+            assert!(
+                register(&env, "a", name).await.is_err(),
+                "{} was registered",
+                name
+            );
+        }
+        assert!(env.command_list().await.is_empty());
+    }
+}
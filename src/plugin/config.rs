@@ -0,0 +1,89 @@
+use std::path::Path;
+
+use super::DiscoveredPlugin;
+
+/// Recursively merges `overrides` into `base`. Tables are merged key by key,
+/// everything else (including arrays) is replaced.
+fn merge(base: &mut toml::Table, overrides: toml::Table) {
+    for (key, value) in overrides {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(overrides)) => {
+                merge(base, overrides)
+            }
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+fn read_table(path: &Path) -> anyhow::Result<Option<toml::Table>> {
+    if !path.is_file() {
+        return Ok(None);
+    }
+    let table = toml::from_str::<toml::Table>(&std::fs::read_to_string(path)?)
+        .map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))?;
+    Ok(Some(table))
+}
+
+/// Loads the plugin's own `config.toml`, with the operator's `<overrides_dir>/<plugin name>.toml`
+/// merged on top. Both files are optional.
+pub fn load_plugin_config(
+    plugin: &DiscoveredPlugin,
+    overrides_dir: &Path,
+) -> anyhow::Result<toml::Table> {
+    let mut config = read_table(&plugin.config_path())?.unwrap_or_default();
+    let overrides_path = overrides_dir.join(format!("{}.toml", plugin.manifest.name));
+    if let Some(overrides) = read_table(&overrides_path)? {
+        merge(&mut config, overrides);
+    }
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(s: &str) -> toml::Table {
+        toml::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn overrides_replace_values() {
+        let mut base = table("greeting = \"hi\"\nmax = 3\nkept = true");
+        merge(
+            &mut base,
+            table("greeting = \"hello\"\nmax = 5\nadded = 1.5"),
+        );
+        assert_eq!(
+            base,
+            table("greeting = \"hello\"\nmax = 5\nkept = true\nadded = 1.5")
+        );
+    }
+
+    #[test]
+    fn nested_tables_are_merged() {
+        let mut base = table("[limits]\nspeed = 10\nvehicles = 2\n[limits.inner]\na = 1\nb = 2");
+        merge(
+            &mut base,
+            table("[limits]\nvehicles = 4\n[limits.inner]\nb = 3"),
+        );
+        assert_eq!(
+            base,
+            table("[limits]\nspeed = 10\nvehicles = 4\n[limits.inner]\na = 1\nb = 3")
+        );
+    }
+
+    #[test]
+    fn arrays_and_mismatched_types_are_replaced() {
+        let mut base = table("maps = [\"a\", \"b\"]\nlimits = { speed = 10 }\nname = \"x\"");
+        merge(
+            &mut base,
+            table("maps = [\"c\"]\nlimits = 5\nname = { first = \"y\" }"),
+        );
+        assert_eq!(
+            base,
+            table("maps = [\"c\"]\nlimits = 5\nname = { first = \"y\" }")
+        );
+    }
+}
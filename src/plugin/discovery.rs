@@ -0,0 +1,256 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use serde::Deserialize;
+
+const MANIFEST_FILE: &str = "plugin.toml";
+const ENTRY_FILE: &str = "main.lua";
+const CONFIG_FILE: &str = "config.toml";
+/// Written to by the plugin itself, so it never counts as a change to the plugin
+const DATA_DIR: &str = "data";
+
+/// The optional `plugin.toml` next to a plugin's `main.lua`.
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct PluginManifest {
+    /// Defaults to the name of the plugin directory
+    pub name: String,
+    pub version: String,
+    pub description: String,
+    /// Plugins that have to be loaded before this one
+    pub dependencies: Vec<String>,
+    /// Plugins with a lower load order are loaded first
+    pub load_order: i32,
+}
+
+#[derive(Debug, Clone)]
+pub struct DiscoveredPlugin {
+    pub manifest: PluginManifest,
+    pub dir: PathBuf,
+}
+
+impl DiscoveredPlugin {
+    pub fn entry_path(&self) -> PathBuf {
+        self.dir.join(ENTRY_FILE)
+    }
+
+    /// The default configuration the plugin ships with.
+    pub fn config_path(&self) -> PathBuf {
+        self.dir.join(CONFIG_FILE)
+    }
+
+    /// The only directory a sandboxed plugin may read and write files in.
+    pub fn data_dir(&self) -> PathBuf {
+        self.dir.join(DATA_DIR)
+    }
+
+    /// Where the plugin's `ngmp.storage` is saved.
+    pub fn storage_path(&self) -> PathBuf {
+        self.data_dir().join("storage.json")
+    }
+}
+
+/// Finds all plugins in `plugins_dir`, which are all directories containing a `main.lua`.
+/// Plugins with a broken manifest are reported and skipped.
+pub fn discover_plugins<P: AsRef<Path>>(plugins_dir: P) -> Vec<DiscoveredPlugin> {
+    let plugins_dir = plugins_dir.as_ref();
+    let entries = match std::fs::read_dir(plugins_dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            error!(
+                "Failed to read plugins directory {}: {}",
+                plugins_dir.display(),
+                e
+            );
+            return Vec::new();
+        }
+    };
+
+    let mut plugins = Vec::new();
+    for entry in entries.flatten() {
+        let dir = entry.path();
+        if !dir.is_dir() || !dir.join(ENTRY_FILE).is_file() {
+            continue;
+        }
+        match discover_plugin(&dir) {
+            Ok(plugin) => plugins.push(plugin),
+            Err(e) => error!("Skipping plugin {}: {}", dir.display(), e),
+        }
+    }
+    plugins
+}
+
+/// Reads the manifest of the plugin in `dir`, if it has one.
+pub fn discover_plugin(dir: &Path) -> anyhow::Result<DiscoveredPlugin> {
+    let manifest_path = dir.join(MANIFEST_FILE);
+    let mut manifest = if manifest_path.is_file() {
+        toml::from_str::<PluginManifest>(&std::fs::read_to_string(&manifest_path)?)?
+    } else {
+        PluginManifest::default()
+    };
+
+    if manifest.name.is_empty() {
+        manifest.name = dir
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+    }
+    Ok(DiscoveredPlugin {
+        manifest,
+        dir: dir.to_path_buf(),
+    })
+}
+
+/// Returns the newest modification time of all files in a plugin directory, including
+/// subdirectories but not the plugin's data directory.
+pub fn last_modified(dir: &Path) -> Option<SystemTime> {
+    let mut newest = None;
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(current) = pending.pop() {
+        let Ok(entries) = std::fs::read_dir(&current) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            // Symlinks aren't followed, so a link back up the tree can't make this loop forever
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+            if file_type.is_dir() {
+                if !(current == dir && entry.file_name() == DATA_DIR) {
+                    pending.push(entry.path());
+                }
+            } else if let Ok(modified) = entry.metadata().and_then(|m| m.modified()) {
+                newest = newest.max(Some(modified));
+            }
+        }
+    }
+    newest
+}
+
+/// Sorts plugins so every plugin comes after its dependencies, otherwise respecting the
+/// load order (and name, to keep things deterministic). Plugins with missing or circular
+/// dependencies are reported and left out.
+pub fn resolve_load_order(mut plugins: Vec<DiscoveredPlugin>) -> Vec<DiscoveredPlugin> {
+    plugins.sort_by(|a, b| {
+        (a.manifest.load_order, &a.manifest.name).cmp(&(b.manifest.load_order, &b.manifest.name))
+    });
+
+    let mut seen = HashSet::new();
+    plugins.retain(|p| {
+        let unique = seen.insert(p.manifest.name.clone());
+        if !unique {
+            error!(
+                "Skipping plugin {}: duplicate plugin name `{}`",
+                p.dir.display(),
+                p.manifest.name
+            );
+        }
+        unique
+    });
+
+    let mut ordered = Vec::with_capacity(plugins.len());
+    let mut placed = HashSet::new();
+    loop {
+        let Some(index) = plugins.iter().position(|p| {
+            p.manifest
+                .dependencies
+                .iter()
+                .all(|dep| placed.contains(dep.as_str()))
+        }) else {
+            break;
+        };
+        let plugin = plugins.remove(index);
+        placed.insert(plugin.manifest.name.clone());
+        ordered.push(plugin);
+    }
+
+    for plugin in plugins {
+        let missing = plugin
+            .manifest
+            .dependencies
+            .iter()
+            .filter(|dep| !placed.contains(dep.as_str()))
+            .cloned()
+            .collect::<Vec<_>>();
+        error!(
+            "Skipping plugin {}: missing or circular dependencies: {}",
+            plugin.manifest.name,
+            missing.join(", ")
+        );
+    }
+
+    ordered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plugin(name: &str, load_order: i32, dependencies: &[&str]) -> DiscoveredPlugin {
+        DiscoveredPlugin {
+            manifest: PluginManifest {
+                name: name.to_string(),
+                load_order,
+                dependencies: dependencies.iter().map(|d| d.to_string()).collect(),
+                ..Default::default()
+            },
+            dir: PathBuf::from(name),
+        }
+    }
+
+    fn names(plugins: &[DiscoveredPlugin]) -> Vec<&str> {
+        plugins.iter().map(|p| p.manifest.name.as_str()).collect()
+    }
+
+    #[test]
+    fn sorts_by_load_order_then_name() {
+        let ordered = resolve_load_order(vec![
+            plugin("c", 0, &[]),
+            plugin("b", 1, &[]),
+            plugin("a", 0, &[]),
+            plugin("z", -1, &[]),
+        ]);
+        assert_eq!(names(&ordered), ["z", "a", "c", "b"]);
+    }
+
+    #[test]
+    fn dependencies_come_first() {
+        let ordered = resolve_load_order(vec![
+            plugin("a", 0, &["b"]),
+            plugin("b", 5, &["c"]),
+            plugin("c", 10, &[]),
+        ]);
+        assert_eq!(names(&ordered), ["c", "b", "a"]);
+    }
+
+    #[test]
+    fn skips_missing_dependencies_and_their_dependents() {
+        let ordered = resolve_load_order(vec![
+            plugin("a", 0, &["missing"]),
+            plugin("b", 0, &["a"]),
+            plugin("c", 0, &[]),
+        ]);
+        assert_eq!(names(&ordered), ["c"]);
+    }
+
+    #[test]
+    fn skips_dependency_cycles() {
+        let ordered = resolve_load_order(vec![
+            plugin("a", 0, &["b"]),
+            plugin("b", 0, &["a"]),
+            plugin("self", 0, &["self"]),
+            plugin("ok", 0, &[]),
+        ]);
+        assert_eq!(names(&ordered), ["ok"]);
+    }
+
+    #[test]
+    fn keeps_only_the_first_plugin_with_a_name() {
+        let mut duplicate = plugin("a", 0, &[]);
+        duplicate.dir = PathBuf::from("other");
+        let ordered = resolve_load_order(vec![plugin("a", 0, &[]), duplicate]);
+        assert_eq!(ordered.len(), 1);
+        assert_eq!(ordered[0].dir, PathBuf::from("a"));
+    }
+}
@@ -0,0 +1,261 @@
+use std::fmt;
+use std::fmt::Write;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use mlua::prelude::*;
+use tokio::sync::Mutex;
+
+use super::watchdog::Watchdog;
+use super::{ApiAction, LuaEnvironment, LuaNgmpApi};
+use crate::config::ConfigPlugins;
+
+/// An error raised by a plugin, with everything needed to track it down.
+pub struct PluginErrorReport {
+    pub plugin: String,
+    /// What the server was calling when the error happened, e.g. the event name
+    pub context: String,
+    pub message: String,
+    pub traceback: Option<String>,
+}
+
+impl fmt::Display for PluginErrorReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Plugin {} errored in {}: {}",
+            self.plugin, self.context, self.message
+        )?;
+        if let Some(traceback) = &self.traceback {
+            write!(f, "\n{}", traceback)?;
+        }
+        Ok(())
+    }
+}
+
+/// Creates the message handler passed to `xpcall`. It runs before the stack is unwound,
+/// so it can still see where the error came from.
+pub(super) fn create_error_handler(lua: &Lua) -> LuaResult<LuaFunction> {
+    lua.create_function(|lua, err: LuaValue| {
+        let message = match &err {
+            LuaValue::String(s) => s.to_string_lossy(),
+            LuaValue::Error(e) => e.to_string(),
+            value => format!("{:?}", value),
+        };
+
+        let mut traceback = String::from("stack traceback:");
+        // Level 0 is this handler itself
+        let mut level = 1;
+        while let Some(debug) = lua.inspect_stack(level) {
+            let source = debug.source();
+            let location = source.short_src.as_deref().unwrap_or("?");
+            let _ = match debug.names().name {
+                Some(name) => write!(
+                    traceback,
+                    "\n\t{}:{}: in function '{}'",
+                    location,
+                    debug.curr_line(),
+                    name
+                ),
+                None => write!(
+                    traceback,
+                    "\n\t{}:{}: in {}",
+                    location,
+                    debug.curr_line(),
+                    source.what
+                ),
+            };
+            level += 1;
+        }
+
+        let report = lua.create_table()?;
+        report.set("message", message)?;
+        report.set("traceback", traceback)?;
+        Ok(report)
+    })
+}
+
+/// Everything needed to call into plugins and report their errors.
+/// It's cheap to clone, so api functions that call into other plugins can keep their own.
+#[derive(Clone)]
+pub(super) struct PluginCaller {
+    ngmp_api: Arc<Mutex<LuaNgmpApi>>,
+    config: ConfigPlugins,
+    watchdog: Option<Watchdog>,
+
+    /// Used to call into plugins, so errors come with a traceback
+    xpcall: LuaFunction,
+    error_handler: LuaFunction,
+}
+
+impl PluginCaller {
+    pub(super) fn new(
+        lua: &Lua,
+        ngmp_api: Arc<Mutex<LuaNgmpApi>>,
+        config: ConfigPlugins,
+        watchdog: Option<Watchdog>,
+    ) -> LuaResult<Self> {
+        Ok(Self {
+            ngmp_api,
+            config,
+            watchdog,
+            xpcall: lua.globals().get::<LuaFunction>("xpcall")?,
+            error_handler: create_error_handler(lua)?,
+        })
+    }
+
+    /// Calls a function of a plugin under the watchdog. Errors are reported against the plugin
+    /// (with a traceback) before being returned, so callers don't have to log them again.
+    pub(super) async fn call_plugin_fn<R: FromLuaMulti>(
+        &self,
+        lua: &Lua,
+        plugin_name: &str,
+        context: &str,
+        func: &LuaFunction,
+        args: impl IntoLuaMulti,
+    ) -> LuaResult<R> {
+        let mut call_args = args.into_lua_multi(lua)?;
+        call_args.push_front(LuaValue::Function(self.error_handler.clone()));
+        call_args.push_front(LuaValue::Function(func.clone()));
+
+        let mut values = self
+            .run_guarded(
+                lua,
+                plugin_name,
+                context,
+                self.xpcall.call_async::<LuaMultiValue>(call_args),
+            )
+            .await?;
+        if let Some(LuaValue::Boolean(true)) = values.pop_front() {
+            return R::from_lua_multi(values, lua);
+        }
+
+        let report = match values.pop_front() {
+            Some(LuaValue::Table(report)) => PluginErrorReport {
+                plugin: plugin_name.to_string(),
+                context: context.to_string(),
+                message: report.get("message").unwrap_or_default(),
+                traceback: report.get("traceback").ok(),
+            },
+            // The error handler itself failed, e.g. because the watchdog aborted it too
+            other => PluginErrorReport {
+                plugin: plugin_name.to_string(),
+                context: context.to_string(),
+                message: format!("{:?}", other),
+                traceback: None,
+            },
+        };
+        let message = report.message.clone();
+        self.report_error(report).await;
+        Err(LuaError::runtime(message))
+    }
+
+    /// Runs Lua code of a plugin under the watchdog (if enabled), reporting the plugin if it
+    /// ran over its time budget.
+    async fn run_guarded<T>(
+        &self,
+        lua: &Lua,
+        plugin_name: &str,
+        what: &str,
+        fut: impl Future<Output = LuaResult<T>>,
+    ) -> LuaResult<T> {
+        let Some(watchdog) = &self.watchdog else {
+            return fut.await;
+        };
+
+        let guard = watchdog.arm(lua);
+        let res = fut.await;
+        if guard.disarm() {
+            self.report_overrun(plugin_name, what, watchdog.budget)
+                .await;
+        }
+        res
+    }
+
+    async fn report_overrun(&self, plugin_name: &str, what: &str, budget: Duration) {
+        error!(
+            "Plugin {} exceeded its time budget of {}ms in {}, the call was aborted",
+            plugin_name,
+            budget.as_millis(),
+            what
+        );
+
+        let max_offenses = self.config.watchdog_max_offenses;
+        let mut lock = self.ngmp_api.lock().await;
+        let Some(plugin) = lock
+            .loaded_plugins
+            .iter_mut()
+            .find(|p| p.info.manifest.name == plugin_name)
+        else {
+            return;
+        };
+
+        plugin.watchdog_offenses += 1;
+        if max_offenses > 0 && plugin.watchdog_offenses == max_offenses {
+            error!(
+                "Plugin {} exceeded its time budget {} times, disabling it",
+                plugin_name, max_offenses
+            );
+            lock.actions
+                .push(ApiAction::DisablePlugin(plugin_name.to_string()));
+        }
+    }
+
+    /// Logs a plugin error and counts it against the plugin.
+    /// Plugins that keep erroring get disabled.
+    async fn report_error(&self, report: PluginErrorReport) {
+        error!("{}", report);
+
+        let error_limit = self.config.error_limit;
+        let window = Duration::from_secs(self.config.error_limit_seconds);
+        let mut lock = self.ngmp_api.lock().await;
+        let Some(plugin) = lock
+            .loaded_plugins
+            .iter_mut()
+            .find(|p| p.info.manifest.name == report.plugin)
+        else {
+            return;
+        };
+
+        plugin.error_count += 1;
+        if error_limit == 0 {
+            return;
+        }
+
+        let now = Instant::now();
+        plugin.recent_errors.push_back(now);
+        while plugin
+            .recent_errors
+            .front()
+            .is_some_and(|time| now.duration_since(*time) > window)
+        {
+            plugin.recent_errors.pop_front();
+        }
+        if plugin.recent_errors.len() >= error_limit as usize {
+            plugin.recent_errors.clear();
+            error!(
+                "Plugin {} errored {} times within {} seconds, disabling it",
+                report.plugin,
+                error_limit,
+                window.as_secs()
+            );
+            lock.actions.push(ApiAction::DisablePlugin(report.plugin));
+        }
+    }
+}
+
+impl LuaEnvironment {
+    /// Calls a function of a plugin, see [`PluginCaller::call_plugin_fn`].
+    pub(super) async fn call_plugin_fn<R: FromLuaMulti>(
+        &self,
+        plugin_name: &str,
+        context: &str,
+        func: &LuaFunction,
+        args: impl IntoLuaMulti,
+    ) -> LuaResult<R> {
+        self.caller
+            .call_plugin_fn(&self.lua, plugin_name, context, func, args)
+            .await
+    }
+}
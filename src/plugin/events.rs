@@ -0,0 +1,266 @@
+use std::cmp::Reverse;
+
+use mlua::prelude::*;
+
+use super::LuaEnvironment;
+
+/// An event handler registered by a plugin through `ngmp.events.on`.
+pub struct EventHandler {
+    plugin: String,
+    priority: i32,
+    func: LuaFunction,
+}
+
+impl LuaEnvironment {
+    /// Creates the `ngmp.events` table for a single plugin.
+    pub(super) fn create_events_table(&self, plugin_name: &str) -> LuaResult<LuaTable> {
+        let events_table = self.lua.create_table()?;
+
+        // on(event, fn, [priority]), handlers with a higher priority run first
+        let on_fn = {
+            let api_ref = self.ngmp_api.clone();
+            let plugin = plugin_name.to_string();
+            self.lua.create_async_function(
+                move |_lua: Lua, (event, func, priority): (String, LuaFunction, Option<i32>)| {
+                    let api = api_ref.clone();
+                    let plugin = plugin.clone();
+                    async move {
+                        let mut lock = api.lock().await;
+                        lock.event_handlers
+                            .entry(event)
+                            .or_default()
+                            .push(EventHandler {
+                                plugin,
+                                priority: priority.unwrap_or(0),
+                                func,
+                            });
+                        Ok(())
+                    }
+                },
+            )?
+        };
+        events_table.set("on", on_fn)?;
+
+        // off(event), removes all of this plugin's handlers for an event
+        let off_fn = {
+            let api_ref = self.ngmp_api.clone();
+            let plugin = plugin_name.to_string();
+            self.lua
+                .create_async_function(move |_lua: Lua, (event,): (String,)| {
+                    let api = api_ref.clone();
+                    let plugin = plugin.clone();
+                    async move {
+                        let mut lock = api.lock().await;
+                        if let Some(handlers) = lock.event_handlers.get_mut(&event) {
+                            handlers.retain(|h| h.plugin != plugin);
+                        }
+                        Ok(())
+                    }
+                })?
+        };
+        events_table.set("off", off_fn)?;
+
+        Ok(events_table)
+    }
+
+    /// Removes every event handler a plugin registered.
+    pub(super) async fn remove_event_handlers(&self, plugin_name: &str) {
+        let mut lock = self.ngmp_api.lock().await;
+        for handlers in lock.event_handlers.values_mut() {
+            handlers.retain(|h| h.plugin != plugin_name);
+        }
+    }
+
+    /// Returns every handler for an event as (plugin, function), highest priority first.
+    /// Besides `ngmp.events.on`, plugins can handle an event by putting a function with the
+    /// event's name in the table their `main.lua` returns. Those count as priority 0.
+    async fn event_handlers(&self, event: &str) -> Vec<(String, LuaFunction)> {
+        let (mut handlers, tables) = {
+            let lock = self.ngmp_api.lock().await;
+            let handlers = lock
+                .event_handlers
+                .get(event)
+                .into_iter()
+                .flatten()
+                .map(|h| (h.plugin.clone(), h.priority, h.func.clone()))
+                .collect::<Vec<_>>();
+            let tables = lock
+                .loaded_plugins
+                .iter()
+                .map(|p| (p.info.manifest.name.clone(), p.table.clone()))
+                .collect::<Vec<_>>();
+            (handlers, tables)
+        };
+
+        for (plugin, table) in tables {
+            if let Ok(func) = table.get::<LuaFunction>(event) {
+                handlers.push((plugin, 0, func));
+            }
+        }
+
+        // Stable, so handlers with the same priority run in load order
+        handlers.sort_by_key(|(_, priority, _)| Reverse(*priority));
+        handlers
+            .into_iter()
+            .map(|(plugin, _, func)| (plugin, func))
+            .collect()
+    }
+
+    /// Calls the handlers of an event one by one.
+    ///
+    /// If the event is cancelable, a handler can return `false` (optionally followed by a
+    /// reason) to stop the event, in which case the reason is returned as the error.
+    /// Any other value a handler returns is passed to `on_return`, which can use it to change
+    /// `state`. The arguments for every handler are built from the current `state`.
+    async fn dispatch_event<S, A: IntoLuaMulti>(
+        &self,
+        event: &str,
+        cancelable: bool,
+        mut state: S,
+        args: impl Fn(&S) -> A,
+        mut on_return: impl FnMut(&mut S, LuaValue),
+    ) -> Result<S, Option<String>> {
+        for (plugin, func) in self.event_handlers(event).await {
+            let res = self
+                .call_plugin_fn::<LuaMultiValue>(&plugin, event, &func, args(&state))
+                .await;
+            // Errors are already reported, a broken handler shouldn't affect the others
+            let Ok(values) = res else {
+                continue;
+            };
+            let mut values = values.into_iter();
+
+            match values.next() {
+                Some(LuaValue::Boolean(false)) if cancelable => {
+                    let reason = match values.next() {
+                        Some(LuaValue::String(s)) => Some(s.to_string_lossy()),
+                        _ => None,
+                    };
+                    return Err(reason);
+                }
+                Some(value) => on_return(&mut state, value),
+                None => {}
+            }
+        }
+        Ok(state)
+    }
+
+    /// Calls every handler of an event, ignoring what they return.
+    async fn call_event<A: IntoLuaMulti + Clone>(&self, event: &str, args: A) {
+        let _ = self
+            .dispatch_event(event, false, (), |_| args.clone(), |_, _| {})
+            .await;
+    }
+
+    /// Calls the handlers of an event until one of them cancels it.
+    async fn call_cancelable_event<A: IntoLuaMulti + Clone>(
+        &self,
+        event: &str,
+        args: A,
+    ) -> Result<(), Option<String>> {
+        self.dispatch_event(event, true, (), |_| args.clone(), |_, _| {})
+            .await
+    }
+
+    /// Asks plugins whether a player may join.
+    /// Returns the reason given by the plugin if the player was rejected.
+    pub async fn event_on_player_connecting(
+        &self,
+        steam_id: u64,
+        name: &str,
+    ) -> Result<(), Option<String>> {
+        self.call_cancelable_event(
+            "onPlayerConnecting",
+            (steam_id.to_string(), name.to_string()),
+        )
+        .await
+    }
+
+    /// Deprecated, `onPlayerConnecting` replaces it. Still called right after it for
+    /// plugins written before it existed, but can't reject the player.
+    pub async fn event_on_player_auth(&self, steam_id: u64, name: &str) {
+        self.call_event("onPlayerAuth", (steam_id.to_string(), name.to_string()))
+            .await;
+    }
+
+    pub async fn event_on_player_join(&self, steam_id: u64, name: &str) {
+        self.call_event("onPlayerJoin", (steam_id.to_string(), name.to_string()))
+            .await;
+    }
+
+    pub async fn event_on_player_disconnect(&self, steam_id: u64, name: &str) {
+        self.call_event(
+            "onPlayerDisconnect",
+            (steam_id.to_string(), name.to_string()),
+        )
+        .await;
+    }
+
+    /// Returns false if a plugin cancelled the spawn.
+    pub async fn event_on_vehicle_spawn(
+        &self,
+        steam_id: u64,
+        vehicle_id: u16,
+        data: &serde_json::Value,
+    ) -> bool {
+        let data = match self.lua.to_value(data) {
+            Ok(data) => data,
+            Err(e) => {
+                error!("Failed to pass vehicle data to plugins: {}", e);
+                LuaValue::Nil
+            }
+        };
+        self.call_cancelable_event("onVehicleSpawn", (steam_id.to_string(), vehicle_id, data))
+            .await
+            .is_ok()
+    }
+
+    /// Called after a vehicle was deleted, by its owner or a plugin.
+    /// Not cancelable, as the owner's client deletes its vehicles before telling the server.
+    pub async fn event_on_vehicle_delete(&self, steam_id: u64, vehicle_id: u16) {
+        self.call_event("onVehicleDelete", (steam_id.to_string(), vehicle_id))
+            .await;
+    }
+
+    /// Lets every plugin look at a chat message before it is sent out.
+    /// A plugin can return `false` to cancel the message, or a string to replace it.
+    /// Returns None if the message was cancelled.
+    pub async fn event_on_chat_message(
+        &self,
+        steam_id: u64,
+        name: &str,
+        message: String,
+    ) -> Option<String> {
+        let sid = steam_id.to_string();
+        self.dispatch_event(
+            "onChatMessage",
+            true,
+            message,
+            |message| (sid.clone(), name.to_string(), message.clone()),
+            |message, value| {
+                if let LuaValue::String(s) = value {
+                    *message = s.to_string_lossy();
+                }
+            },
+        )
+        .await
+        .ok()
+    }
+
+    /// Called once every server tick, `dt` is the time since the last tick in seconds.
+    pub async fn event_on_tick(&self, dt: f64) {
+        self.call_event("onTick", dt).await;
+    }
+
+    pub async fn event_on_server_shutdown(&self) {
+        self.call_event("onServerShutdown", ()).await;
+    }
+
+    pub async fn event_on_map_changing(&self, map: &str) {
+        self.call_event("onMapChanging", map.to_string()).await;
+    }
+
+    pub async fn event_on_map_changed(&self, map: &str) {
+        self.call_event("onMapChanged", map.to_string()).await;
+    }
+}
@@ -0,0 +1,382 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use mlua::prelude::*;
+use tokio::sync::{mpsc, Mutex};
+
+use super::{LuaEnvironment, LuaNgmpApi};
+use crate::config::ConfigPlugins;
+
+/// Responses with a bigger body are dropped, so a plugin can't run the server out of memory
+const MAX_BODY_SIZE: usize = 4 * 1024 * 1024;
+
+pub struct HttpResponse {
+    status: u16,
+    headers: HashMap<String, String>,
+    body: String,
+}
+
+type HttpResult = Result<HttpResponse, String>;
+
+/// A request that is still in flight, along with the function to call once it is done.
+struct PendingRequest {
+    plugin: String,
+    callback: Option<LuaFunction>,
+}
+
+/// Everything needed to run plugin HTTP requests in the background.
+/// Requests run on their own tokio task, their results are picked up once per tick.
+pub struct HttpState {
+    client: reqwest::Client,
+    next_id: u64,
+    pending: HashMap<u64, PendingRequest>,
+    /// Plugin name -> when its recent requests were sent
+    rate_limits: HashMap<String, VecDeque<Instant>>,
+    results_tx: mpsc::UnboundedSender<(u64, HttpResult)>,
+    results_rx: mpsc::UnboundedReceiver<(u64, HttpResult)>,
+}
+
+impl HttpState {
+    pub fn new() -> Self {
+        let (results_tx, results_rx) = mpsc::unbounded_channel();
+        Self {
+            // Redirects are not followed, they could point to a host outside the allow-list.
+            // Plugins get the redirect response instead and can decide for themselves.
+            client: reqwest::Client::builder()
+                .redirect(reqwest::redirect::Policy::none())
+                .build()
+                .expect("Failed to build http client!"),
+            next_id: 0,
+            pending: HashMap::new(),
+            rate_limits: HashMap::new(),
+            results_tx,
+            results_rx,
+        }
+    }
+
+    /// Returns false if the plugin already sent too many requests in the configured window.
+    /// A limit of 0 disables it.
+    fn allow(&mut self, plugin: &str, config: &ConfigPlugins) -> bool {
+        if config.http_rate_limit_requests == 0 {
+            return true;
+        }
+
+        let now = Instant::now();
+        let window = Duration::from_secs(config.http_rate_limit_seconds);
+        let sent = self.rate_limits.entry(plugin.to_string()).or_default();
+        while sent
+            .front()
+            .is_some_and(|sent| now.duration_since(*sent) > window)
+        {
+            sent.pop_front();
+        }
+
+        if sent.len() >= config.http_rate_limit_requests {
+            return false;
+        }
+        sent.push_back(now);
+        true
+    }
+}
+
+/// Checks a host against the allow-list. Entries like `*.example.com` also allow subdomains.
+fn is_allowed_host(allowed_hosts: &[String], host: &str) -> bool {
+    let host = host.to_ascii_lowercase();
+    allowed_hosts.iter().any(|allowed| {
+        let allowed = allowed.to_ascii_lowercase();
+        match allowed.strip_prefix("*.") {
+            Some(domain) => host == domain || host.ends_with(&format!(".{}", domain)),
+            None => host == allowed,
+        }
+    })
+}
+
+async fn send_request(request: reqwest::RequestBuilder) -> HttpResult {
+    let mut response = request.send().await.map_err(|e| e.to_string())?;
+    let status = response.status().as_u16();
+    let headers = response
+        .headers()
+        .iter()
+        .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+        .collect();
+    let too_big = || format!("response body is larger than {} bytes", MAX_BODY_SIZE);
+    if response
+        .content_length()
+        .is_some_and(|len| len > MAX_BODY_SIZE as u64)
+    {
+        return Err(too_big());
+    }
+    // The content length can't be trusted, so the limit is checked while reading as well
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await.map_err(|e| e.to_string())? {
+        if body.len() + chunk.len() > MAX_BODY_SIZE {
+            return Err(too_big());
+        }
+        body.extend_from_slice(&chunk);
+    }
+    let body = String::from_utf8_lossy(&body).into_owned();
+    Ok(HttpResponse {
+        status,
+        headers,
+        body,
+    })
+}
+
+impl LuaEnvironment {
+    /// Creates the `ngmp.http` table for a single plugin.
+    /// Requests never block, the callback is called with `(response, nil)` or `(nil, error)`
+    /// on a later tick.
+    pub(super) fn create_http_table(&self, plugin_name: &str) -> LuaResult<LuaTable> {
+        let http_table = self.lua.create_table()?;
+
+        // get(url, [callback], [headers])
+        let get_fn = {
+            let ctx = self.http_request_context(plugin_name);
+            self.lua.create_async_function(
+                move |lua: Lua,
+                      (url, callback, headers): (
+                    String,
+                    Option<LuaFunction>,
+                    Option<HashMap<String, String>>,
+                )| {
+                    let ctx = ctx.clone();
+                    async move {
+                        ctx.start_request(&lua, reqwest::Method::GET, url, None, callback, headers)
+                            .await
+                    }
+                },
+            )?
+        };
+        http_table.set("get", get_fn)?;
+
+        // post(url, body, [callback], [headers]), tables are sent as JSON
+        let post_fn = {
+            let ctx = self.http_request_context(plugin_name);
+            self.lua.create_async_function(
+                move |lua: Lua,
+                      (url, body, callback, headers): (
+                    String,
+                    LuaValue,
+                    Option<LuaFunction>,
+                    Option<HashMap<String, String>>,
+                )| {
+                    let ctx = ctx.clone();
+                    async move {
+                        ctx.start_request(
+                            &lua,
+                            reqwest::Method::POST,
+                            url,
+                            Some(body),
+                            callback,
+                            headers,
+                        )
+                        .await
+                    }
+                },
+            )?
+        };
+        http_table.set("post", post_fn)?;
+
+        Ok(http_table)
+    }
+
+    fn http_request_context(&self, plugin_name: &str) -> HttpRequestContext {
+        HttpRequestContext {
+            api: self.ngmp_api.clone(),
+            config: self.config.clone(),
+            plugin: plugin_name.to_string(),
+        }
+    }
+
+    /// Forgets about the requests a plugin still has in flight.
+    /// Its rate limit history is kept, so reloading a plugin doesn't reset its limit.
+    pub(super) async fn cancel_http_requests(&self, plugin_name: &str) {
+        let mut lock = self.ngmp_api.lock().await;
+        lock.http.pending.retain(|_, r| r.plugin != plugin_name);
+    }
+
+    /// Calls the callbacks of every request that finished since the last tick.
+    pub async fn run_http_callbacks(&self) {
+        let mut finished = Vec::new();
+        {
+            let mut lock = self.ngmp_api.lock().await;
+            while let Ok((id, result)) = lock.http.results_rx.try_recv() {
+                // The plugin may have been unloaded in the meantime
+                if let Some(request) = lock.http.pending.remove(&id) {
+                    finished.push((request, result));
+                }
+            }
+        }
+
+        for (request, result) in finished {
+            let Some(callback) = request.callback else {
+                if let Err(e) = result {
+                    warn!("HTTP request of plugin {} failed: {}", request.plugin, e);
+                }
+                continue;
+            };
+
+            let args = match result {
+                Ok(response) => match self.http_response_to_lua(response) {
+                    Ok(table) => (Some(table), None),
+                    Err(e) => {
+                        error!(
+                            "Failed to pass http response to plugin {}: {}",
+                            request.plugin, e
+                        );
+                        continue;
+                    }
+                },
+                Err(e) => (None, Some(e)),
+            };
+            let _: LuaResult<()> = self
+                .call_plugin_fn(&request.plugin, "http callback", &callback, args)
+                .await;
+        }
+    }
+
+    fn http_response_to_lua(&self, response: HttpResponse) -> LuaResult<LuaTable> {
+        let table = self.lua.create_table()?;
+        table.set("status", response.status)?;
+        table.set("headers", response.headers)?;
+        table.set("body", response.body)?;
+        Ok(table)
+    }
+}
+
+/// What the `ngmp.http` functions of a single plugin need to start a request.
+#[derive(Clone)]
+struct HttpRequestContext {
+    api: Arc<Mutex<LuaNgmpApi>>,
+    config: ConfigPlugins,
+    plugin: String,
+}
+
+impl HttpRequestContext {
+    async fn start_request(
+        &self,
+        lua: &Lua,
+        method: reqwest::Method,
+        url: String,
+        body: Option<LuaValue>,
+        callback: Option<LuaFunction>,
+        headers: Option<HashMap<String, String>>,
+    ) -> LuaResult<()> {
+        let parsed = reqwest::Url::parse(&url)
+            .map_err(|e| LuaError::runtime(format!("invalid url `{}`: {}", url, e)))?;
+        if !matches!(parsed.scheme(), "http" | "https") {
+            return Err(LuaError::runtime(format!(
+                "unsupported url scheme `{}`",
+                parsed.scheme()
+            )));
+        }
+        let host = parsed.host_str().unwrap_or_default();
+        if !is_allowed_host(&self.config.http_allowed_hosts, host) {
+            return Err(LuaError::runtime(format!(
+                "host `{}` is not in the http allow-list",
+                host
+            )));
+        }
+
+        let mut lock = self.api.lock().await;
+        if !lock.http.allow(&self.plugin, &self.config) {
+            return Err(LuaError::runtime("too many http requests, try again later"));
+        }
+
+        let mut request = lock
+            .http
+            .client
+            .request(method, parsed)
+            .timeout(Duration::from_millis(self.config.http_timeout_ms));
+        for (name, value) in headers.unwrap_or_default() {
+            request = request.header(name, value);
+        }
+        request = match body {
+            None | Some(LuaValue::Nil) => request,
+            Some(LuaValue::String(s)) => request.body(s.as_bytes().to_vec()),
+            Some(value) => request.json(&lua.from_value::<serde_json::Value>(value)?),
+        };
+
+        lock.http.next_id += 1;
+        let id = lock.http.next_id;
+        lock.http.pending.insert(
+            id,
+            PendingRequest {
+                plugin: self.plugin.clone(),
+                callback,
+            },
+        );
+
+        let results_tx = lock.http.results_tx.clone();
+        tokio::spawn(async move {
+            let result = send_request(request).await;
+            // The receiver only goes away together with the whole Lua environment
+            let _ = results_tx.send((id, result));
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hosts(hosts: &[&str]) -> Vec<String> {
+        hosts.iter().map(|h| h.to_string()).collect()
+    }
+
+    #[test]
+    fn exact_hosts_match_only_themselves() {
+        let allowed = hosts(&["api.example.com"]);
+        assert!(is_allowed_host(&allowed, "api.example.com"));
+        assert!(is_allowed_host(&allowed, "API.Example.com"));
+        assert!(!is_allowed_host(&allowed, "example.com"));
+        assert!(!is_allowed_host(&allowed, "sub.api.example.com"));
+        assert!(!is_allowed_host(&allowed, "api.example.com.evil.net"));
+    }
+
+    #[test]
+    fn wildcards_match_the_domain_and_its_subdomains() {
+        let allowed = hosts(&["*.Example.com"]);
+        assert!(is_allowed_host(&allowed, "example.com"));
+        assert!(is_allowed_host(&allowed, "api.example.com"));
+        assert!(is_allowed_host(&allowed, "a.b.example.com"));
+        assert!(!is_allowed_host(&allowed, "notexample.com"));
+        assert!(!is_allowed_host(&allowed, "example.com.evil.net"));
+    }
+
+    #[test]
+    fn nothing_is_allowed_by_default() {
+        assert!(!is_allowed_host(&[], "example.com"));
+        assert!(!is_allowed_host(&[], ""));
+    }
+
+    fn rate_limit(requests: usize) -> ConfigPlugins {
+        ConfigPlugins {
+            http_rate_limit_requests: requests,
+            http_rate_limit_seconds: 60,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn requests_over_the_rate_limit_are_refused() {
+        let mut http = HttpState::new();
+        let config = rate_limit(2);
+        assert!(http.allow("a", &config));
+        assert!(http.allow("a", &config));
+        assert!(!http.allow("a", &config));
+        // Every plugin has its own limit
+        assert!(http.allow("b", &config));
+    }
+
+    #[test]
+    fn a_rate_limit_of_zero_allows_everything() {
+        let mut http = HttpState::new();
+        let config = rate_limit(0);
+        for _ in 0..100 {
+            assert!(http.allow("a", &config));
+        }
+    }
+}
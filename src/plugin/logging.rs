@@ -0,0 +1,174 @@
+use std::collections::HashSet;
+use std::fmt::Write;
+
+use log::Level;
+use mlua::prelude::*;
+
+use super::LuaEnvironment;
+
+/// Tables nested deeper than this are not printed
+const MAX_DEPTH: usize = 8;
+
+/// Formats a value like Lua's `tostring`, except that tables are pretty-printed.
+fn format_value(value: &LuaValue) -> String {
+    let mut output = String::new();
+    write_value(&mut output, value, 0, &mut HashSet::new());
+    output
+}
+
+fn write_value(
+    output: &mut String,
+    value: &LuaValue,
+    depth: usize,
+    seen: &mut HashSet<*const std::ffi::c_void>,
+) {
+    match value {
+        LuaValue::Nil => output.push_str("nil"),
+        LuaValue::Boolean(b) => output.push_str(&b.to_string()),
+        LuaValue::Integer(i) => output.push_str(&i.to_string()),
+        LuaValue::Number(n) => output.push_str(&n.to_string()),
+        LuaValue::String(s) => output.push_str(&s.to_string_lossy()),
+        LuaValue::Table(table) => write_table(output, table, depth, seen),
+        value => {
+            let _ = write!(output, "{}: {:p}", value.type_name(), value.to_pointer());
+        }
+    }
+}
+
+fn write_table(
+    output: &mut String,
+    table: &LuaTable,
+    depth: usize,
+    seen: &mut HashSet<*const std::ffi::c_void>,
+) {
+    // Tables referencing themselves would otherwise be printed forever
+    if depth >= MAX_DEPTH || !seen.insert(table.to_pointer()) {
+        let _ = write!(output, "table: {:p}", table.to_pointer());
+        return;
+    }
+
+    let mut pairs = table.pairs::<LuaValue, LuaValue>().flatten().peekable();
+    if pairs.peek().is_none() {
+        output.push_str("{}");
+        seen.remove(&table.to_pointer());
+        return;
+    }
+
+    let indent = "  ".repeat(depth + 1);
+    output.push_str("{\n");
+    for (key, value) in pairs {
+        output.push_str(&indent);
+        match &key {
+            LuaValue::String(s) => output.push_str(&s.to_string_lossy()),
+            key => {
+                output.push('[');
+                write_value(output, key, depth + 1, seen);
+                output.push(']');
+            }
+        }
+        output.push_str(" = ");
+        write_value(output, &value, depth + 1, seen);
+        output.push_str(",\n");
+    }
+    output.push_str(&"  ".repeat(depth));
+    output.push('}');
+    seen.remove(&table.to_pointer());
+}
+
+/// Joins all arguments with tabs, like Lua's `print` does.
+fn format_args(args: &LuaMultiValue) -> String {
+    args.iter().map(format_value).collect::<Vec<_>>().join("\t")
+}
+
+impl LuaEnvironment {
+    /// Creates a logging function that logs at `level`, with the plugin name as the target.
+    fn create_log_fn(&self, plugin_name: &str, level: Level) -> LuaResult<LuaFunction> {
+        let target = plugin_name.to_string();
+        self.lua.create_function(move |_lua, args: LuaMultiValue| {
+            log!(target: &target, level, "{}", format_args(&args));
+            Ok(())
+        })
+    }
+
+    /// Creates the `print` function plugins see, which logs at info level.
+    pub(super) fn create_print_fn(&self, plugin_name: &str) -> LuaResult<LuaFunction> {
+        self.create_log_fn(plugin_name, Level::Info)
+    }
+
+    /// Creates the `ngmp.log` table for a single plugin.
+    pub(super) fn create_log_table(&self, plugin_name: &str) -> LuaResult<LuaTable> {
+        let log_table = self.lua.create_table()?;
+        log_table.set("debug", self.create_log_fn(plugin_name, Level::Debug)?)?;
+        log_table.set("info", self.create_log_fn(plugin_name, Level::Info)?)?;
+        log_table.set("warn", self.create_log_fn(plugin_name, Level::Warn)?)?;
+        log_table.set("error", self.create_log_fn(plugin_name, Level::Error)?)?;
+        Ok(log_table)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format_lua(lua: &Lua, code: &str) -> String {
+        format_value(&lua.load(code).eval::<LuaValue>().unwrap())
+    }
+
+    #[test]
+    fn formats_nested_tables() {
+        let lua = Lua::new();
+        assert_eq!(format_lua(&lua, "return {}"), "{}");
+        assert_eq!(
+            format_lua(&lua, "return { a = { b = 'c' } }"),
+            "{\n  a = {\n    b = c,\n  },\n}"
+        );
+        assert_eq!(format_lua(&lua, "return { 10 }"), "{\n  [1] = 10,\n}");
+    }
+
+    #[test]
+    fn marks_tables_referencing_themselves() {
+        let lua = Lua::new();
+        let table = lua
+            .load("local t = {} t.self = t return t")
+            .eval::<LuaTable>()
+            .unwrap();
+        assert_eq!(
+            format_value(&LuaValue::Table(table.clone())),
+            format!("{{\n  self = table: {:p},\n}}", table.to_pointer())
+        );
+    }
+
+    #[test]
+    fn prints_tables_seen_twice_without_a_cycle() {
+        let lua = Lua::new();
+        let output = format_lua(&lua, "local shared = { x = 1 } return { shared, shared }");
+        assert_eq!(output.matches("x = 1").count(), 2, "{}", output);
+    }
+
+    #[test]
+    fn stops_at_the_maximum_depth() {
+        let lua = Lua::new();
+        let output = format_lua(
+            &lua,
+            "local t = {} local cur = t for i = 1, 20 do cur.next = {} cur = cur.next end return t",
+        );
+        assert_eq!(
+            output.matches("next = {").count(),
+            MAX_DEPTH - 1,
+            "{}",
+            output
+        );
+        assert_eq!(output.matches("next = table: ").count(), 1, "{}", output);
+    }
+
+    #[test]
+    fn joins_arguments_with_tabs() {
+        let lua = Lua::new();
+        let args = lua
+            .load("return 1, 'two', nil, true, 1.5")
+            .eval::<LuaMultiValue>()
+            .unwrap();
+        assert_eq!(format_args(&args), "1\ttwo\tnil\ttrue\t1.5");
+        assert_eq!(format_args(&LuaMultiValue::new()), "");
+    }
+}
@@ -0,0 +1,78 @@
+use mlua::prelude::*;
+
+use super::players::parse_steam_id;
+use super::{ApiAction, LuaEnvironment};
+use crate::permissions::{PermissionChange, Permissions};
+
+impl LuaEnvironment {
+    /// Replaces the permissions plugins see. Called by the server whenever they change.
+    pub async fn set_permissions(&self, permissions: Permissions) {
+        self.ngmp_api.lock().await.permissions = permissions;
+    }
+
+    /// Creates the `ngmp.permissions` table.
+    /// Only trusted plugins can change permissions, anyone can look them up.
+    pub(super) fn create_permissions_table(&self, trusted: bool) -> LuaResult<LuaTable> {
+        let permissions_table = self.lua.create_table()?;
+
+        let has_fn = {
+            let api_ref = self.ngmp_api.clone();
+            self.lua.create_async_function(
+                move |_lua: Lua, (steam_id, node): (String, String)| {
+                    let api = api_ref.clone();
+                    async move {
+                        let steam_id = parse_steam_id(&steam_id)?;
+                        Ok(api.lock().await.permissions.has_permission(steam_id, &node))
+                    }
+                },
+            )?
+        };
+        permissions_table.set("has", has_fn)?;
+
+        // Includes the default role and roles inherited through other roles
+        let get_roles_fn = {
+            let api_ref = self.ngmp_api.clone();
+            self.lua
+                .create_async_function(move |_lua: Lua, (steam_id,): (String,)| {
+                    let api = api_ref.clone();
+                    async move {
+                        let steam_id = parse_steam_id(&steam_id)?;
+                        Ok(api.lock().await.permissions.roles_of(steam_id))
+                    }
+                })?
+        };
+        permissions_table.set("get_roles", get_roles_fn)?;
+
+        let changes: [(&str, fn(String) -> PermissionChange); 4] = [
+            ("add_role", PermissionChange::AddRole),
+            ("remove_role", PermissionChange::RemoveRole),
+            ("grant", PermissionChange::Grant),
+            ("revoke", PermissionChange::Revoke),
+        ];
+        for (name, change) in changes {
+            let api_ref = self.ngmp_api.clone();
+            let change_fn = self.lua.create_async_function(
+                move |_lua: Lua, (steam_id, value): (String, String)| {
+                    let api = api_ref.clone();
+                    async move {
+                        let steam_id = parse_steam_id(&steam_id)?;
+                        api.lock().await.actions.push(ApiAction::ChangePermissions {
+                            steam_id,
+                            change: change(value),
+                        });
+                        Ok(())
+                    }
+                },
+            )?;
+            permissions_table.set(name, change_fn)?;
+        }
+        self.restrict_to_trusted(
+            trusted,
+            &permissions_table,
+            "permissions",
+            &changes.map(|(name, _)| name),
+        )?;
+
+        Ok(permissions_table)
+    }
+}
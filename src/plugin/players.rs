@@ -0,0 +1,182 @@
+use mlua::prelude::*;
+
+use super::{ApiAction, LuaEnvironment};
+
+/// A snapshot of a connected player, kept up to date by the server every tick.
+#[derive(Debug, Clone)]
+pub struct PlayerInfo {
+    pub steam_id: u64,
+    pub name: String,
+    pub avatar_hash: String,
+    pub synced: bool,
+    pub vehicles: Vec<u16>,
+}
+
+impl PlayerInfo {
+    fn to_lua_table(&self, lua: &Lua) -> LuaResult<LuaTable> {
+        let table = lua.create_table()?;
+        table.set("steam_id", self.steam_id.to_string())?;
+        table.set("name", self.name.clone())?;
+        table.set("avatar_hash", self.avatar_hash.clone())?;
+        table.set("synced", self.synced)?;
+        table.set("vehicles", self.vehicles.clone())?;
+        Ok(table)
+    }
+}
+
+/// Steam IDs don't fit in a Lua number, so they are passed around as strings.
+pub(super) fn parse_steam_id(steam_id: &str) -> LuaResult<u64> {
+    steam_id
+        .parse::<u64>()
+        .map_err(|_| LuaError::runtime(format!("invalid steam id `{}`", steam_id)))
+}
+
+impl LuaEnvironment {
+    /// Replaces the player snapshot plugins see.
+    /// Returns the players that were in the previous snapshot, but aren't anymore.
+    pub async fn set_players(&self, players: Vec<PlayerInfo>) -> Vec<PlayerInfo> {
+        let mut lock = self.ngmp_api.lock().await;
+        let mut previous = std::mem::take(&mut lock.players);
+        for player in players {
+            previous.remove(&player.steam_id);
+            lock.players.insert(player.steam_id, player);
+        }
+        previous.into_values().collect()
+    }
+
+    /// Creates the `ngmp.players` table.
+    pub(super) fn create_players_table(&self) -> LuaResult<LuaTable> {
+        let players_table = self.lua.create_table()?;
+
+        let list_fn = {
+            let api_ref = self.ngmp_api.clone();
+            self.lua.create_async_function(move |_lua: Lua, _: ()| {
+                let api = api_ref.clone();
+                async move {
+                    let lock = api.lock().await;
+                    Ok(lock
+                        .players
+                        .keys()
+                        .map(|id| id.to_string())
+                        .collect::<Vec<_>>())
+                }
+            })?
+        };
+        players_table.set("list", list_fn)?;
+
+        let get_fn = {
+            let api_ref = self.ngmp_api.clone();
+            self.lua
+                .create_async_function(move |lua: Lua, (steam_id,): (String,)| {
+                    let api = api_ref.clone();
+                    async move {
+                        let steam_id = parse_steam_id(&steam_id)?;
+                        let player = api.lock().await.players.get(&steam_id).cloned();
+                        player.map(|p| p.to_lua_table(&lua)).transpose()
+                    }
+                })?
+        };
+        players_table.set("get", get_fn)?;
+
+        let find_fn = {
+            let api_ref = self.ngmp_api.clone();
+            self.lua
+                .create_async_function(move |_lua: Lua, (name,): (String,)| {
+                    let api = api_ref.clone();
+                    async move {
+                        let lock = api.lock().await;
+                        Ok(lock
+                            .players
+                            .values()
+                            .find(|p| p.name.eq_ignore_ascii_case(&name))
+                            .map(|p| p.steam_id.to_string()))
+                    }
+                })?
+        };
+        players_table.set("find", find_fn)?;
+
+        let get_name_fn = self.create_player_getter(|p| p.name.clone())?;
+        players_table.set("get_name", get_name_fn)?;
+        let get_avatar_fn = self.create_player_getter(|p| p.avatar_hash.clone())?;
+        players_table.set("get_avatar", get_avatar_fn)?;
+        let get_vehicles_fn = self.create_player_getter(|p| p.vehicles.clone())?;
+        players_table.set("get_vehicles", get_vehicles_fn)?;
+        let is_synced_fn = self.create_player_getter(|p| p.synced)?;
+        players_table.set("is_synced", is_synced_fn)?;
+
+        let kick_fn = {
+            let api_ref = self.ngmp_api.clone();
+            self.lua.create_async_function(
+                move |_lua: Lua, (steam_id, reason): (String, Option<String>)| {
+                    let api = api_ref.clone();
+                    async move {
+                        let steam_id = parse_steam_id(&steam_id)?;
+                        let reason = reason.unwrap_or_else(|| String::from("Kicked by server"));
+                        api.lock()
+                            .await
+                            .actions
+                            .push(ApiAction::KickPlayer { steam_id, reason });
+                        Ok(())
+                    }
+                },
+            )?
+        };
+        players_table.set("kick", kick_fn)?;
+
+        let send_message_fn = {
+            let api_ref = self.ngmp_api.clone();
+            self.lua.create_async_function(
+                move |_lua: Lua, (steam_id, message): (String, String)| {
+                    let api = api_ref.clone();
+                    async move {
+                        let steam_id = parse_steam_id(&steam_id)?;
+                        api.lock().await.actions.push(ApiAction::SendMessage {
+                            steam_id: Some(steam_id),
+                            message,
+                        });
+                        Ok(())
+                    }
+                },
+            )?
+        };
+        players_table.set("send_message", send_message_fn)?;
+
+        let send_message_all_fn = {
+            let api_ref = self.ngmp_api.clone();
+            self.lua
+                .create_async_function(move |_lua: Lua, (message,): (String,)| {
+                    let api = api_ref.clone();
+                    async move {
+                        api.lock().await.actions.push(ApiAction::SendMessage {
+                            steam_id: None,
+                            message,
+                        });
+                        Ok(())
+                    }
+                })?
+        };
+        players_table.set("send_message_all", send_message_all_fn)?;
+
+        Ok(players_table)
+    }
+
+    /// Creates a function taking a steam id, returning a single value from that player's info,
+    /// or nil if they aren't connected.
+    fn create_player_getter<R, F>(&self, getter: F) -> LuaResult<LuaFunction>
+    where
+        R: IntoLua + 'static,
+        F: Fn(&PlayerInfo) -> R + Clone + 'static,
+    {
+        let api_ref = self.ngmp_api.clone();
+        self.lua
+            .create_async_function(move |_lua: Lua, (steam_id,): (String,)| {
+                let api = api_ref.clone();
+                let getter = getter.clone();
+                async move {
+                    let steam_id = parse_steam_id(&steam_id)?;
+                    let lock = api.lock().await;
+                    Ok(lock.players.get(&steam_id).map(getter))
+                }
+            })
+    }
+}
@@ -0,0 +1,401 @@
+use std::path::{Component, Path, PathBuf};
+
+use mlua::prelude::*;
+
+use super::LuaEnvironment;
+
+/// Base library functions sandboxed plugins are allowed to use.
+/// Notably missing are `load`, `loadfile`, `dofile`, `require`, `setfenv`, `getfenv` and `collectgarbage`.
+const SAFE_GLOBALS: &[&str] = &[
+    "_VERSION",
+    "assert",
+    "error",
+    "ipairs",
+    "next",
+    "pairs",
+    "pcall",
+    "print",
+    "rawequal",
+    "rawget",
+    "rawset",
+    "select",
+    "setmetatable",
+    "tonumber",
+    "tostring",
+    "type",
+    "unpack",
+    "xpcall",
+];
+
+/// Libraries sandboxed plugins get a copy of.
+const SAFE_LIBRARIES: &[&str] = &["string", "table", "math", "bit", "coroutine"];
+
+/// The only parts of `os` sandboxed plugins get, as the rest can run commands or touch files.
+const SAFE_OS_FUNCTIONS: &[&str] = &["clock", "date", "difftime", "time"];
+
+/// Wraps `loadstring` so it only accepts source code (no bytecode) and runs it in the
+/// plugin's own environment instead of the global one.
+const SAFE_LOADSTRING: &str = r#"
+local loadstring, setfenv, env = ...
+return function(code, chunkname)
+    if type(code) ~= "string" then
+        return nil, "only strings can be loaded"
+    end
+    if code:byte(1) == 27 then
+        return nil, "loading bytecode is not allowed"
+    end
+    local fn, err = loadstring(code, chunkname)
+    if not fn then
+        return nil, err
+    end
+    return setfenv(fn, env)
+end
+"#;
+
+/// Wraps `getmetatable`, as the metatable of strings is shared by every plugin. Its `__index`
+/// is the real `string` library, so handing it out would let one plugin replace string
+/// methods for everyone. Plugins get a metatable pointing at their own copy instead.
+const SAFE_GETMETATABLE: &str = r#"
+local getmetatable, string_lib = ...
+local string_mt = { __index = string_lib }
+return function(value)
+    if type(value) == "string" then
+        return string_mt
+    end
+    return getmetatable(value)
+end
+"#;
+
+/// Resolves a path given by a plugin inside its data directory.
+/// Absolute paths and paths escaping the data directory are refused.
+fn resolve_data_path(data_dir: &Path, path: &str) -> LuaResult<PathBuf> {
+    let relative = Path::new(path);
+    if relative
+        .components()
+        .any(|c| !matches!(c, Component::Normal(_) | Component::CurDir))
+    {
+        return Err(LuaError::runtime(format!(
+            "path `{}` is outside of the plugin data directory",
+            path
+        )));
+    }
+    Ok(data_dir.join(relative))
+}
+
+impl LuaEnvironment {
+    /// Trusted plugins are not sandboxed and have access to the full standard library.
+    pub(super) fn is_trusted(&self, plugin_name: &str) -> bool {
+        !self.config.sandbox || self.config.trusted.iter().any(|t| t == plugin_name)
+    }
+
+    /// Replaces api functions that change who may do what on the server with ones that only
+    /// raise an error, unless the plugin is trusted.
+    pub(super) fn restrict_to_trusted(
+        &self,
+        trusted: bool,
+        table: &LuaTable,
+        table_name: &str,
+        names: &[&str],
+    ) -> LuaResult<()> {
+        if trusted {
+            return Ok(());
+        }
+        for name in names {
+            let message = format!(
+                "ngmp.{}.{} is only available to trusted plugins",
+                table_name, name
+            );
+            let denied_fn = self.lua.create_function(move |_lua, _: LuaMultiValue| {
+                Err::<(), _>(LuaError::runtime(message.clone()))
+            })?;
+            table.set(*name, denied_fn)?;
+        }
+        Ok(())
+    }
+
+    /// Fills a plugin environment with only the safe parts of the standard library.
+    pub(super) fn populate_sandboxed_env(&self, env: &LuaTable) -> LuaResult<()> {
+        let globals = self.lua.globals();
+
+        for name in SAFE_GLOBALS {
+            env.raw_set(*name, globals.raw_get::<LuaValue>(*name)?)?;
+        }
+        for name in SAFE_LIBRARIES {
+            if let Some(lib) = globals.raw_get::<Option<LuaTable>>(*name)? {
+                env.raw_set(*name, self.shallow_copy(&lib)?)?;
+            }
+        }
+
+        let os = globals.raw_get::<LuaTable>("os")?;
+        let safe_os = self.lua.create_table()?;
+        for name in SAFE_OS_FUNCTIONS {
+            safe_os.raw_set(*name, os.raw_get::<LuaValue>(*name)?)?;
+        }
+        env.raw_set("os", safe_os)?;
+
+        let loadstring_fn: LuaFunction =
+            self.lua.load(SAFE_LOADSTRING).set_name("=sandbox").call((
+                globals.raw_get::<LuaFunction>("loadstring")?,
+                globals.raw_get::<LuaFunction>("setfenv")?,
+                env.clone(),
+            ))?;
+        env.raw_set("loadstring", loadstring_fn.clone())?;
+        env.raw_set("load", loadstring_fn)?;
+
+        let getmetatable_fn: LuaFunction = self
+            .lua
+            .load(SAFE_GETMETATABLE)
+            .set_name("=sandbox")
+            .call((
+                globals.raw_get::<LuaFunction>("getmetatable")?,
+                env.raw_get::<LuaTable>("string")?,
+            ))?;
+        env.raw_set("getmetatable", getmetatable_fn)?;
+
+        Ok(())
+    }
+
+    /// Creates the `ngmp.fs` table, giving a plugin file access inside its own data directory.
+    pub(super) fn create_fs_table(&self, data_dir: PathBuf) -> LuaResult<LuaTable> {
+        let fs_table = self.lua.create_table()?;
+
+        let read_fn = {
+            let data_dir = data_dir.clone();
+            self.lua.create_function(move |_lua, (path,): (String,)| {
+                let path = resolve_data_path(&data_dir, &path)?;
+                match std::fs::read_to_string(path) {
+                    Ok(content) => Ok(Some(content)),
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                    Err(e) => Err(LuaError::external(e)),
+                }
+            })?
+        };
+        fs_table.set("read", read_fn)?;
+
+        let write_fn = {
+            let data_dir = data_dir.clone();
+            self.lua
+                .create_function(move |_lua, (path, content): (String, LuaString)| {
+                    let path = resolve_data_path(&data_dir, &path)?;
+                    if let Some(parent) = path.parent() {
+                        std::fs::create_dir_all(parent).map_err(LuaError::external)?;
+                    }
+                    std::fs::write(path, &*content.as_bytes()).map_err(LuaError::external)
+                })?
+        };
+        fs_table.set("write", write_fn)?;
+
+        let exists_fn = {
+            let data_dir = data_dir.clone();
+            self.lua.create_function(move |_lua, (path,): (String,)| {
+                Ok(resolve_data_path(&data_dir, &path)?.exists())
+            })?
+        };
+        fs_table.set("exists", exists_fn)?;
+
+        let list_fn = {
+            let data_dir = data_dir.clone();
+            self.lua
+                .create_function(move |_lua, (path,): (Option<String>,)| {
+                    let path = resolve_data_path(&data_dir, path.as_deref().unwrap_or("."))?;
+                    let entries = match std::fs::read_dir(path) {
+                        Ok(entries) => entries,
+                        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+                        Err(e) => return Err(LuaError::external(e)),
+                    };
+                    Ok(entries
+                        .flatten()
+                        .map(|entry| entry.file_name().to_string_lossy().to_string())
+                        .collect::<Vec<_>>())
+                })?
+        };
+        fs_table.set("list", list_fn)?;
+
+        let remove_fn = {
+            self.lua.create_function(move |_lua, (path,): (String,)| {
+                let path = resolve_data_path(&data_dir, &path)?;
+                if path.is_dir() {
+                    std::fs::remove_dir_all(path).map_err(LuaError::external)
+                } else {
+                    std::fs::remove_file(path).map_err(LuaError::external)
+                }
+            })?
+        };
+        fs_table.set("remove", remove_fn)?;
+
+        Ok(fs_table)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ConfigPlugins;
+
+    /// Runs `code` in a fresh sandboxed plugin environment.
+    fn run_sandboxed<R: FromLuaMulti>(env: &LuaEnvironment, code: &str) -> LuaResult<R> {
+        let sandbox = env.lua.create_table()?;
+        env.populate_sandboxed_env(&sandbox)?;
+        env.lua.load(code).set_environment(sandbox).call(())
+    }
+
+    fn new_env() -> LuaEnvironment {
+        LuaEnvironment::new(ConfigPlugins::default()).unwrap()
+    }
+
+    #[test]
+    fn resolves_paths_inside_the_data_directory() {
+        let data_dir = Path::new("plugins/example/data");
+        assert_eq!(
+            resolve_data_path(data_dir, "scores.json").unwrap(),
+            data_dir.join("scores.json")
+        );
+        assert_eq!(
+            resolve_data_path(data_dir, "./sub/dir/file.txt").unwrap(),
+            data_dir.join("./sub/dir/file.txt")
+        );
+        assert_eq!(
+            resolve_data_path(data_dir, ".").unwrap(),
+            data_dir.join(".")
+        );
+    }
+
+    #[test]
+    fn refuses_paths_escaping_the_data_directory() {
+        let data_dir = Path::new("plugins/example/data");
+        for path in ["../main.lua", "sub/../../main.lua", "/etc/passwd", ".."] {
+            assert!(
+                resolve_data_path(data_dir, path).is_err(),
+                "{} was accepted",
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn io_and_debug_are_unreachable() {
+        let env = new_env();
+        let (io, debug): (LuaValue, LuaValue) = run_sandboxed(&env, "return io, debug").unwrap();
+        assert!(io.is_nil());
+        assert!(debug.is_nil());
+        // Not through the string metatable either
+        let io: LuaValue = run_sandboxed(&env, "return getmetatable('').__index.io").unwrap();
+        assert!(io.is_nil());
+    }
+
+    #[test]
+    fn os_only_has_the_safe_functions() {
+        let env = new_env();
+        for name in ["execute", "remove", "rename", "exit", "getenv", "tmpname"] {
+            let value: LuaValue = run_sandboxed(&env, &format!("return os.{}", name)).unwrap();
+            assert!(value.is_nil(), "os.{} is reachable", name);
+        }
+        let err = run_sandboxed::<()>(&env, "os.execute('echo hi')").unwrap_err();
+        assert!(err.to_string().contains("execute"), "{}", err);
+
+        let time: f64 = run_sandboxed(&env, "return os.time()").unwrap();
+        assert!(time > 0.0);
+    }
+
+    #[test]
+    fn files_can_not_be_loaded() {
+        let env = new_env();
+        for name in ["dofile", "loadfile", "require", "setfenv", "getfenv"] {
+            let value: LuaValue = run_sandboxed(&env, &format!("return {}", name)).unwrap();
+            assert!(value.is_nil(), "{} is reachable", name);
+        }
+    }
+
+    #[test]
+    fn loaded_code_stays_in_the_sandbox() {
+        let env = new_env();
+        let (from_load, from_loadstring): (LuaValue, LuaValue) = run_sandboxed(
+            &env,
+            "return load('return io')(), loadstring('return os.execute')()",
+        )
+        .unwrap();
+        assert!(from_load.is_nil());
+        assert!(from_loadstring.is_nil());
+    }
+
+    #[test]
+    fn bytecode_is_refused() {
+        let env = new_env();
+        let (func, err): (LuaValue, String) = run_sandboxed(
+            &env,
+            "return loadstring(string.dump(function() return io end))",
+        )
+        .unwrap();
+        assert!(func.is_nil());
+        assert_eq!(err, "loading bytecode is not allowed");
+
+        let (func, _): (LuaValue, String) = run_sandboxed(&env, "return load('\\27LJ')").unwrap();
+        assert!(func.is_nil());
+    }
+
+    #[test]
+    fn the_shared_string_metatable_is_not_handed_out() {
+        let env = new_env();
+        run_sandboxed::<()>(
+            &env,
+            "getmetatable('').__index.upper = function() return 'pwned' end",
+        )
+        .unwrap();
+
+        let real_upper: LuaFunction = env
+            .lua
+            .globals()
+            .get::<LuaTable>("string")
+            .unwrap()
+            .get("upper")
+            .unwrap();
+        assert_eq!(real_upper.call::<String>("abc").unwrap(), "ABC");
+        let upper: String = run_sandboxed(&env, "return ('abc'):upper()").unwrap();
+        assert_eq!(upper, "ABC");
+    }
+
+    #[tokio::test]
+    async fn only_trusted_plugins_can_change_permissions_and_bans() {
+        let env = new_env();
+        let permissions = env.create_permissions_table(false).unwrap();
+        let bans = env.create_bans_table(false).unwrap();
+        let whitelist = env.create_whitelist_table(false).unwrap();
+        let steam_id = "76561198000000000";
+
+        let grant: LuaFunction = permissions.get("grant").unwrap();
+        let err = grant
+            .call_async::<()>((steam_id, "command.kick"))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("trusted"), "{}", err);
+        let ban: LuaFunction = bans.get("ban").unwrap();
+        assert!(ban.call_async::<()>(steam_id).await.is_err());
+        let set_enabled: LuaFunction = whitelist.get("set_enabled").unwrap();
+        assert!(set_enabled.call_async::<()>(true).await.is_err());
+        assert!(env.take_actions().await.is_empty());
+
+        // Looking things up is still fine
+        let has: LuaFunction = permissions.get("has").unwrap();
+        assert!(!has
+            .call_async::<bool>((steam_id, "command.kick"))
+            .await
+            .unwrap());
+        let get: LuaFunction = bans.get("get").unwrap();
+        assert!(get
+            .call_async::<Option<LuaTable>>(steam_id)
+            .await
+            .unwrap()
+            .is_none());
+
+        let trusted_grant: LuaFunction = env
+            .create_permissions_table(true)
+            .unwrap()
+            .get("grant")
+            .unwrap();
+        trusted_grant
+            .call_async::<()>((steam_id, "command.kick"))
+            .await
+            .unwrap();
+        assert_eq!(env.take_actions().await.len(), 1);
+    }
+}
@@ -0,0 +1,296 @@
+use std::path::PathBuf;
+
+use mlua::prelude::*;
+
+use super::LuaEnvironment;
+
+type JsonMap = serde_json::Map<String, serde_json::Value>;
+
+/// A plugin's key-value store, kept in memory and written to `data/storage.json`.
+pub struct PluginStorage {
+    path: PathBuf,
+    values: JsonMap,
+    /// Whether there are changes that haven't been written to disk yet
+    dirty: bool,
+}
+
+impl PluginStorage {
+    /// Loads the store from disk, or starts out empty if there is nothing there yet.
+    pub fn open(path: PathBuf) -> Self {
+        let values = match std::fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str::<JsonMap>(&content).unwrap_or_else(|e| {
+                // Keep the broken file around instead of overwriting it on the next flush
+                let backup = path.with_extension("json.broken");
+                error!(
+                    "Failed to parse {}, moving it to {}: {}",
+                    path.display(),
+                    backup.display(),
+                    e
+                );
+                if let Err(e) = std::fs::rename(&path, &backup) {
+                    error!("{}", e);
+                }
+                JsonMap::new()
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => JsonMap::new(),
+            Err(e) => {
+                error!("Failed to read {}: {}", path.display(), e);
+                JsonMap::new()
+            }
+        };
+
+        Self {
+            path,
+            values,
+            dirty: false,
+        }
+    }
+
+    /// Writes the store to disk if anything changed. The file is replaced atomically, so a
+    /// crash halfway through never leaves a half written store behind.
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let content = serde_json::to_string_pretty(&self.values)?;
+        let tmp_path = self.path.with_extension("json.tmp");
+        std::fs::write(&tmp_path, content)?;
+        std::fs::rename(&tmp_path, &self.path)?;
+
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+impl LuaEnvironment {
+    /// Creates the `ngmp.storage` table for a single plugin.
+    pub(super) fn create_storage_table(&self, plugin_name: &str) -> LuaResult<LuaTable> {
+        let storage_table = self.lua.create_table()?;
+
+        let get_fn = {
+            let api_ref = self.ngmp_api.clone();
+            let plugin = plugin_name.to_string();
+            self.lua
+                .create_async_function(move |lua: Lua, (key,): (String,)| {
+                    let api = api_ref.clone();
+                    let plugin = plugin.clone();
+                    async move {
+                        let value = {
+                            let lock = api.lock().await;
+                            lock.storages
+                                .get(&plugin)
+                                .and_then(|s| s.values.get(&key).cloned())
+                        };
+                        match value {
+                            Some(value) => lua.to_value(&value),
+                            None => Ok(LuaValue::Nil),
+                        }
+                    }
+                })?
+        };
+        storage_table.set("get", get_fn)?;
+
+        // set(key, value), setting a key to nil removes it
+        let set_fn = {
+            let api_ref = self.ngmp_api.clone();
+            let plugin = plugin_name.to_string();
+            self.lua
+                .create_async_function(move |lua: Lua, (key, value): (String, LuaValue)| {
+                    let api = api_ref.clone();
+                    let plugin = plugin.clone();
+                    async move {
+                        let value = match value {
+                            LuaValue::Nil => None,
+                            value => Some(lua.from_value::<serde_json::Value>(value)?),
+                        };
+                        let mut lock = api.lock().await;
+                        let storage = lock.storages.get_mut(&plugin).ok_or_else(|| {
+                            LuaError::runtime(format!("plugin `{}` has no storage", plugin))
+                        })?;
+                        match value {
+                            Some(value) => {
+                                storage.values.insert(key, value);
+                            }
+                            None => {
+                                storage.values.remove(&key);
+                            }
+                        }
+                        storage.dirty = true;
+                        Ok(())
+                    }
+                })?
+        };
+        storage_table.set("set", set_fn)?;
+
+        let keys_fn = {
+            let api_ref = self.ngmp_api.clone();
+            let plugin = plugin_name.to_string();
+            self.lua.create_async_function(move |_lua: Lua, _: ()| {
+                let api = api_ref.clone();
+                let plugin = plugin.clone();
+                async move {
+                    let lock = api.lock().await;
+                    Ok(lock
+                        .storages
+                        .get(&plugin)
+                        .map(|s| s.values.keys().cloned().collect::<Vec<_>>())
+                        .unwrap_or_default())
+                }
+            })?
+        };
+        storage_table.set("keys", keys_fn)?;
+
+        // Changes are flushed to disk automatically, but plugins can force it
+        let flush_fn = {
+            let api_ref = self.ngmp_api.clone();
+            let plugin = plugin_name.to_string();
+            self.lua.create_async_function(move |_lua: Lua, _: ()| {
+                let api = api_ref.clone();
+                let plugin = plugin.clone();
+                async move {
+                    let mut lock = api.lock().await;
+                    if let Some(storage) = lock.storages.get_mut(&plugin) {
+                        storage.flush().map_err(LuaError::external)?;
+                    }
+                    Ok(())
+                }
+            })?
+        };
+        storage_table.set("flush", flush_fn)?;
+
+        Ok(storage_table)
+    }
+
+    /// Writes every plugin's storage to disk.
+    pub async fn flush_storage(&self) {
+        let mut lock = self.ngmp_api.lock().await;
+        for (plugin, storage) in lock.storages.iter_mut() {
+            if let Err(e) = storage.flush() {
+                error!("Failed to save storage of plugin {}: {}", plugin, e);
+            }
+        }
+    }
+
+    /// Flushes and closes a plugin's storage.
+    pub(super) async fn close_storage(&self, plugin_name: &str) {
+        let mut lock = self.ngmp_api.lock().await;
+        if let Some(mut storage) = lock.storages.remove(plugin_name) {
+            if let Err(e) = storage.flush() {
+                error!("Failed to save storage of plugin {}: {}", plugin_name, e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ConfigPlugins;
+
+    /// An empty directory for the files of a single test.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("ngmp-storage-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn flushed_values_survive_reopening() {
+        let dir = test_dir("reopen");
+        let path = dir.join("data").join("storage.json");
+
+        let mut storage = PluginStorage::open(path.clone());
+        assert!(storage.values.is_empty());
+        storage
+            .values
+            .insert(String::from("score"), serde_json::json!(42));
+        storage
+            .values
+            .insert(String::from("names"), serde_json::json!(["a", "b"]));
+        storage.dirty = true;
+        storage.flush().unwrap();
+        assert!(!storage.dirty);
+
+        // Written to a temporary file first, which was then renamed into place
+        assert!(path.exists());
+        assert!(!path.with_extension("json.tmp").exists());
+
+        let reopened = PluginStorage::open(path);
+        assert_eq!(reopened.values, storage.values);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn nothing_is_written_without_changes() {
+        let dir = test_dir("clean");
+        let path = dir.join("storage.json");
+
+        let mut storage = PluginStorage::open(path.clone());
+        storage.flush().unwrap();
+        assert!(!path.exists());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn a_broken_file_is_moved_aside() {
+        let dir = test_dir("broken");
+        let path = dir.join("storage.json");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(&path, "{ \"score\": ").unwrap();
+
+        let mut storage = PluginStorage::open(path.clone());
+        assert!(storage.values.is_empty());
+        assert!(!path.exists());
+        let backup = path.with_extension("json.broken");
+        assert_eq!(std::fs::read_to_string(&backup).unwrap(), "{ \"score\": ");
+
+        // The next flush doesn't touch the backup
+        storage
+            .values
+            .insert(String::from("score"), serde_json::json!(1));
+        storage.dirty = true;
+        storage.flush().unwrap();
+        assert_eq!(std::fs::read_to_string(&backup).unwrap(), "{ \"score\": ");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn plugins_only_see_their_own_keys() {
+        let env = LuaEnvironment::new(ConfigPlugins::default()).unwrap();
+        let dir = test_dir("isolation");
+        {
+            let mut lock = env.ngmp_api.lock().await;
+            for plugin in ["a", "b"] {
+                let storage = PluginStorage::open(dir.join(plugin).join("storage.json"));
+                lock.storages.insert(plugin.to_string(), storage);
+            }
+        }
+        let a = env.create_storage_table("a").unwrap();
+        let b = env.create_storage_table("b").unwrap();
+
+        let set: LuaFunction = a.get("set").unwrap();
+        set.call_async::<()>(("key", "from a")).await.unwrap();
+
+        let get_a: LuaFunction = a.get("get").unwrap();
+        let get_b: LuaFunction = b.get("get").unwrap();
+        let keys_b: LuaFunction = b.get("keys").unwrap();
+        assert_eq!(
+            get_a.call_async::<Option<String>>("key").await.unwrap(),
+            Some(String::from("from a"))
+        );
+        assert_eq!(
+            get_b.call_async::<Option<String>>("key").await.unwrap(),
+            None
+        );
+        assert!(keys_b
+            .call_async::<Vec<String>>(())
+            .await
+            .unwrap()
+            .is_empty());
+    }
+}
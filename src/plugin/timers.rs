@@ -0,0 +1,117 @@
+use std::time::{Duration, Instant};
+
+use mlua::prelude::*;
+
+use super::LuaEnvironment;
+
+/// A function a plugin scheduled through `ngmp.timer`.
+pub struct Timer {
+    plugin: String,
+    func: LuaFunction,
+    next_run: Instant,
+    /// None for timers that only run once
+    interval: Option<Duration>,
+}
+
+impl LuaEnvironment {
+    /// Creates the `ngmp.timer` table for a single plugin.
+    /// Timers are checked once per server tick, so they can run up to a tick late.
+    pub(super) fn create_timer_table(&self, plugin_name: &str) -> LuaResult<LuaTable> {
+        let timer_table = self.lua.create_table()?;
+
+        timer_table.set("after", self.create_timer_fn(plugin_name, false)?)?;
+        timer_table.set("every", self.create_timer_fn(plugin_name, true)?)?;
+
+        // cancel(id), returns whether a timer was cancelled
+        let cancel_fn = {
+            let api_ref = self.ngmp_api.clone();
+            let plugin = plugin_name.to_string();
+            self.lua
+                .create_async_function(move |_lua: Lua, (id,): (u64,)| {
+                    let api = api_ref.clone();
+                    let plugin = plugin.clone();
+                    async move {
+                        let mut lock = api.lock().await;
+                        // Plugins can only cancel their own timers
+                        if lock.timers.get(&id).is_some_and(|t| t.plugin == plugin) {
+                            lock.timers.remove(&id);
+                            return Ok(true);
+                        }
+                        Ok(false)
+                    }
+                })?
+        };
+        timer_table.set("cancel", cancel_fn)?;
+
+        Ok(timer_table)
+    }
+
+    /// Creates `after(ms, fn)` or `every(ms, fn)`, which return the ID of the new timer.
+    fn create_timer_fn(&self, plugin_name: &str, repeat: bool) -> LuaResult<LuaFunction> {
+        let api_ref = self.ngmp_api.clone();
+        let plugin = plugin_name.to_string();
+        self.lua
+            .create_async_function(move |_lua: Lua, (ms, func): (u64, LuaFunction)| {
+                let api = api_ref.clone();
+                let plugin = plugin.clone();
+                async move {
+                    let delay = Duration::from_millis(ms);
+                    let mut lock = api.lock().await;
+                    lock.next_timer_id += 1;
+                    let id = lock.next_timer_id;
+                    lock.timers.insert(
+                        id,
+                        Timer {
+                            plugin,
+                            func,
+                            next_run: Instant::now() + delay,
+                            interval: repeat.then_some(delay),
+                        },
+                    );
+                    Ok(id)
+                }
+            })
+    }
+
+    /// Removes every timer a plugin scheduled.
+    pub(super) async fn remove_timers(&self, plugin_name: &str) {
+        let mut lock = self.ngmp_api.lock().await;
+        lock.timers.retain(|_, t| t.plugin != plugin_name);
+    }
+
+    /// Runs every timer that is due. Called by the server once per tick.
+    /// A timer cancelled by an earlier one in the same tick doesn't run anymore.
+    pub async fn run_timers(&self) {
+        let now = Instant::now();
+        let mut due = {
+            let lock = self.ngmp_api.lock().await;
+            lock.timers
+                .iter()
+                .filter(|(_, timer)| timer.next_run <= now)
+                .map(|(id, timer)| (timer.next_run, *id))
+                .collect::<Vec<_>>()
+        };
+        due.sort();
+
+        for (_, id) in due {
+            let (plugin, func) = {
+                let mut lock = self.ngmp_api.lock().await;
+                let Some(timer) = lock.timers.get_mut(&id) else {
+                    continue;
+                };
+                let call = (timer.plugin.clone(), timer.func.clone());
+                match timer.interval {
+                    Some(interval) => {
+                        // Don't try to catch up on runs we missed
+                        timer.next_run = (timer.next_run + interval).max(now);
+                    }
+                    None => {
+                        lock.timers.remove(&id);
+                    }
+                }
+                call
+            };
+            let _: LuaResult<()> = self.call_plugin_fn(&plugin, "timer", &func, ()).await;
+        }
+    }
+}
@@ -0,0 +1,159 @@
+use std::sync::Arc;
+
+use mlua::prelude::*;
+
+use super::players::parse_steam_id;
+use super::{ApiAction, LuaEnvironment};
+
+/// A snapshot of a spawned vehicle, kept up to date by the server every tick.
+#[derive(Debug, Clone)]
+pub struct VehicleInfo {
+    pub owner: u64,
+    pub vehicle_id: u16,
+    /// The `VehicleData` the vehicle was spawned with
+    pub data: Arc<serde_json::Value>,
+    pub pos: [f32; 3],
+    pub rot: [f32; 4],
+    pub vel: [f32; 3],
+    pub rvel: [f32; 3],
+}
+
+impl VehicleInfo {
+    fn transform_to_lua_table(&self, lua: &Lua) -> LuaResult<LuaTable> {
+        let table = lua.create_table()?;
+        table.set("pos", self.pos)?;
+        table.set("rot", self.rot)?;
+        table.set("vel", self.vel)?;
+        table.set("rvel", self.rvel)?;
+        Ok(table)
+    }
+
+    fn to_lua_table(&self, lua: &Lua) -> LuaResult<LuaTable> {
+        let table = lua.create_table()?;
+        table.set("owner", self.owner.to_string())?;
+        table.set("id", self.vehicle_id)?;
+        table.set("data", lua.to_value(&*self.data)?)?;
+        table.set("transform", self.transform_to_lua_table(lua)?)?;
+        Ok(table)
+    }
+}
+
+impl LuaEnvironment {
+    /// Replaces the vehicle snapshot plugins see.
+    pub async fn set_vehicles(&self, vehicles: Vec<VehicleInfo>) {
+        let mut lock = self.ngmp_api.lock().await;
+        lock.vehicles.clear();
+        lock.vehicles
+            .extend(vehicles.into_iter().map(|v| ((v.owner, v.vehicle_id), v)));
+    }
+
+    /// Creates the `ngmp.vehicles` table.
+    pub(super) fn create_vehicles_table(&self) -> LuaResult<LuaTable> {
+        let vehicles_table = self.lua.create_table()?;
+
+        // Returns a list of `{ owner = steam_id, id = vehicle_id }`, optionally only for one player
+        let list_fn = {
+            let api_ref = self.ngmp_api.clone();
+            self.lua
+                .create_async_function(move |lua: Lua, (steam_id,): (Option<String>,)| {
+                    let api = api_ref.clone();
+                    async move {
+                        let owner = steam_id.as_deref().map(parse_steam_id).transpose()?;
+                        let lock = api.lock().await;
+                        let list = lua.create_table()?;
+                        for vehicle in lock.vehicles.values() {
+                            if owner.is_some_and(|owner| owner != vehicle.owner) {
+                                continue;
+                            }
+                            let entry = lua.create_table()?;
+                            entry.set("owner", vehicle.owner.to_string())?;
+                            entry.set("id", vehicle.vehicle_id)?;
+                            list.push(entry)?;
+                        }
+                        Ok(list)
+                    }
+                })?
+        };
+        vehicles_table.set("list", list_fn)?;
+
+        let get_fn = {
+            let api_ref = self.ngmp_api.clone();
+            self.lua.create_async_function(
+                move |lua: Lua, (steam_id, vehicle_id): (String, u16)| {
+                    let api = api_ref.clone();
+                    async move {
+                        let key = (parse_steam_id(&steam_id)?, vehicle_id);
+                        let vehicle = api.lock().await.vehicles.get(&key).cloned();
+                        vehicle.map(|v| v.to_lua_table(&lua)).transpose()
+                    }
+                },
+            )?
+        };
+        vehicles_table.set("get", get_fn)?;
+
+        let get_transform_fn = {
+            let api_ref = self.ngmp_api.clone();
+            self.lua.create_async_function(
+                move |lua: Lua, (steam_id, vehicle_id): (String, u16)| {
+                    let api = api_ref.clone();
+                    async move {
+                        let key = (parse_steam_id(&steam_id)?, vehicle_id);
+                        let vehicle = api.lock().await.vehicles.get(&key).cloned();
+                        vehicle.map(|v| v.transform_to_lua_table(&lua)).transpose()
+                    }
+                },
+            )?
+        };
+        vehicles_table.set("get_transform", get_transform_fn)?;
+
+        let delete_fn = {
+            let api_ref = self.ngmp_api.clone();
+            self.lua.create_async_function(
+                move |_lua: Lua, (steam_id, vehicle_id): (String, u16)| {
+                    let api = api_ref.clone();
+                    async move {
+                        let steam_id = parse_steam_id(&steam_id)?;
+                        api.lock().await.actions.push(ApiAction::DeleteVehicle {
+                            steam_id,
+                            vehicle_id,
+                        });
+                        Ok(())
+                    }
+                },
+            )?
+        };
+        vehicles_table.set("delete", delete_fn)?;
+
+        // set_position(steam_id, vehicle_id, pos, [rot]), with positional arrays like the ones
+        // `get_transform` returns: pos is `{ 1.0, 2.0, 3.0 }` (x, y, z), not `{ x = 1.0, ... }`,
+        // and rot a quaternion `{ x, y, z, w }` in the same order
+        let set_position_fn = {
+            let api_ref = self.ngmp_api.clone();
+            self.lua.create_async_function(
+                move |_lua: Lua,
+                      (steam_id, vehicle_id, pos, rot): (
+                    String,
+                    u16,
+                    [f32; 3],
+                    Option<[f32; 4]>,
+                )| {
+                    let api = api_ref.clone();
+                    async move {
+                        let steam_id = parse_steam_id(&steam_id)?;
+                        api.lock().await.actions.push(ApiAction::TeleportVehicle {
+                            steam_id,
+                            vehicle_id,
+                            pos,
+                            rot,
+                        });
+                        Ok(())
+                    }
+                },
+            )?
+        };
+        vehicles_table.set("set_position", set_position_fn.clone())?;
+        vehicles_table.set("teleport", set_position_fn)?;
+
+        Ok(vehicles_table)
+    }
+}
@@ -0,0 +1,156 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use mlua::prelude::*;
+
+/// How many VM instructions run between two watchdog checks
+const CHECK_INTERVAL: u32 = 1000;
+
+#[derive(Default)]
+struct WatchdogState {
+    deadline: Option<Instant>,
+    tripped: bool,
+}
+
+/// Aborts Lua calls that run longer than their time budget, so a plugin stuck in a loop
+/// can't hang the server tick.
+#[derive(Clone)]
+pub struct Watchdog {
+    state: Arc<Mutex<WatchdogState>>,
+    pub budget: Duration,
+}
+
+impl Watchdog {
+    /// Installs the watchdog hook on the Lua state.
+    /// This turns off the LuaJIT compiler, as hooks are never called from compiled code.
+    pub fn install(lua: &Lua, budget: Duration) -> LuaResult<Self> {
+        lua.load("if jit then jit.off() end").exec()?;
+
+        let state = Arc::new(Mutex::new(WatchdogState::default()));
+        set_hook(lua, &state, CHECK_INTERVAL);
+
+        Ok(Self { state, budget })
+    }
+
+    /// Starts the clock for a call into Lua. The clock stops when the guard is dropped.
+    /// Calls can be nested, e.g. when a plugin sends a message to another one. The nested
+    /// call can't run past the deadline of the outer one, which gets its deadline back after.
+    pub fn arm<'a>(&'a self, lua: &'a Lua) -> WatchdogGuard<'a> {
+        let mut state = self.state.lock().unwrap();
+        let previous = state.deadline;
+        let deadline = Instant::now() + self.budget;
+        state.deadline = Some(previous.map_or(deadline, |previous| previous.min(deadline)));
+        state.tripped = false;
+        WatchdogGuard {
+            watchdog: self,
+            lua,
+            previous,
+        }
+    }
+
+    fn reset(&self, lua: &Lua, previous: Option<Instant>) -> bool {
+        let tripped = {
+            let mut state = self.state.lock().unwrap();
+            state.deadline = previous;
+            std::mem::take(&mut state.tripped)
+        };
+        if tripped {
+            set_hook(lua, &self.state, CHECK_INTERVAL);
+        }
+        tripped
+    }
+}
+
+/// Keeps the watchdog armed. Dropping it stops the clock, so a call that gets cancelled
+/// halfway doesn't leave the deadline behind for the next one.
+pub struct WatchdogGuard<'a> {
+    watchdog: &'a Watchdog,
+    lua: &'a Lua,
+    /// Deadline of the call this one is nested in
+    previous: Option<Instant>,
+}
+
+impl WatchdogGuard<'_> {
+    /// Stops the clock. Returns true if the call ran over its budget and was aborted.
+    pub fn disarm(self) -> bool {
+        self.watchdog.reset(self.lua, self.previous)
+    }
+}
+
+impl Drop for WatchdogGuard<'_> {
+    fn drop(&mut self) {
+        self.watchdog.reset(self.lua, self.previous);
+    }
+}
+
+fn set_hook(lua: &Lua, state: &Arc<Mutex<WatchdogState>>, interval: u32) {
+    let state = state.clone();
+    lua.set_hook(
+        LuaHookTriggers::new().every_nth_instruction(interval),
+        move |lua, _debug| check(lua, &state),
+    );
+}
+
+fn check(lua: &Lua, state: &Arc<Mutex<WatchdogState>>) -> LuaResult<LuaVmState> {
+    let tripped = {
+        let mut state = state.lock().unwrap();
+        match state.deadline {
+            _ if state.tripped => false,
+            Some(deadline) if Instant::now() >= deadline => {
+                state.tripped = true;
+                true
+            }
+            _ => return Ok(LuaVmState::Continue),
+        }
+    };
+
+    if tripped {
+        // Once over budget, every following instruction raises the error again, so a
+        // plugin can't catch it with pcall and keep going
+        set_hook(lua, state, 1);
+    }
+    Err(LuaError::runtime("plugin exceeded its time budget"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aborts_calls_running_over_budget() {
+        let lua = Lua::new();
+        let watchdog = Watchdog::install(&lua, Duration::from_millis(20)).unwrap();
+
+        let guard = watchdog.arm(&lua);
+        let err = lua.load("while true do end").exec().unwrap_err();
+        assert!(err.to_string().contains("time budget"), "{}", err);
+        assert!(guard.disarm());
+
+        // Once disarmed, the next call gets a fresh budget
+        let guard = watchdog.arm(&lua);
+        lua.load("local x = 0 for i = 1, 1000 do x = x + i end")
+            .exec()
+            .unwrap();
+        assert!(!guard.disarm());
+    }
+
+    #[test]
+    fn nested_calls_keep_the_outer_deadline() {
+        let lua = Lua::new();
+        let watchdog = Watchdog::install(&lua, Duration::from_millis(20)).unwrap();
+
+        let outer = watchdog.arm(&lua);
+        let inner = watchdog.arm(&lua);
+        lua.load("return 1").exec().unwrap();
+        assert!(!inner.disarm());
+
+        // The outer call is still timed after the nested one returned
+        let err = lua.load("while true do end").exec().unwrap_err();
+        assert!(err.to_string().contains("time budget"), "{}", err);
+        assert!(outer.disarm());
+
+        let guard = watchdog.arm(&lua);
+        lua.load("return 1").exec().unwrap();
+        assert!(!guard.disarm());
+    }
+}
@@ -1,7 +1,10 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU16, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, RwLock};
 
 use serde::{Deserialize, Serialize};
 
@@ -9,7 +12,49 @@ use ngmp_protocol_impl::server_launcher::gameplay::{VehicleData, VehicleUpdatePa
 use ngmp_protocol_impl::server_launcher::Packet;
 use ngmp_protocol_impl::{connection::*, server_launcher};
 
-use crate::{http::User, plugin::LuaEnvironment};
+use crate::bans::BanList;
+use crate::config::{Config, ConfigGeneral};
+use crate::connection_limits::ConnectionSlot;
+use crate::console::ServerCommand;
+use crate::http::User;
+use crate::permissions::Permissions;
+use crate::plugin::{ApiAction, LuaEnvironment, PlayerInfo, VehicleInfo};
+use chat::ChatRateLimit;
+use rotation::MapRotation;
+
+mod bans;
+mod chat;
+mod commands;
+mod map;
+mod permissions;
+mod plugins;
+mod rotation;
+mod vehicles;
+
+pub use commands::is_builtin_command;
+
+/// Permission for taking one of the reserved slots
+pub const RESERVED_SLOT_PERMISSION: &str = "slots.reserved";
+
+/// Whether a player can join with `online` other players on the server, without going over
+/// `max_players`. The reserved slots are only open to players that are `reserved`.
+pub fn slot_available(general: &ConfigGeneral, online: usize, reserved: bool) -> bool {
+    if general.max_players == 0 {
+        return true;
+    }
+    let limit = if reserved {
+        general.max_players
+    } else {
+        general.max_players.saturating_sub(general.reserved_slots)
+    };
+    online < limit
+}
+
+/// Generates a new ID for packets that expect a `ConfirmationPacket` in return.
+pub fn next_confirm_id() -> u16 {
+    static NEXT_CONFIRM_ID: AtomicU16 = AtomicU16::new(1);
+    NEXT_CONFIRM_ID.fetch_add(1, Ordering::Relaxed)
+}
 
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct VehicleTransformData {
@@ -27,21 +72,38 @@ pub struct VehicleTransformData {
 
 pub struct Vehicle {
     veh_data: VehicleData,
+    /// `veh_data` as JSON, shared with plugins so it doesn't have to be serialized every tick
+    data_json: Arc<serde_json::Value>,
 
     latest_transform: VehicleTransformData,
     latest_runtime: VehicleUpdatePacket,
+    /// Transforms from the owner are ignored until then, so they don't undo a teleport
+    /// before it reached them
+    teleported_until: Option<Instant>,
 }
 
 impl Vehicle {
     pub fn new(veh_data: VehicleData) -> Self {
+        let data_json = serde_json::to_value(&veh_data).unwrap_or_else(|e| {
+            error!("Failed to serialize vehicle data: {}", e);
+            serde_json::Value::Null
+        });
         Self {
             veh_data,
+            data_json: Arc::new(data_json),
             latest_transform: VehicleTransformData::default(),
             latest_runtime: VehicleUpdatePacket::default(),
+            teleported_until: None,
         }
     }
 }
 
+/// A `LoadMapPacket` the client has yet to confirm.
+pub struct PendingMapLoad {
+    confirm_id: u16,
+    deadline: Instant,
+}
+
 pub struct Client {
     pub tcp_conn: TcpConnection<Packet>,
     pub udp_addr: SocketAddr,
@@ -49,9 +111,19 @@ pub struct Client {
     pub steam_id: u64,
     pub user: User,
 
+    /// Whether the client has loaded the current map
     pub synced: bool,
 
+    /// The last map this client was told to load
+    pub map: String,
+    pub map_load: Option<PendingMapLoad>,
+
+    pub chat_rate_limit: ChatRateLimit,
+
     pub vehicles: HashMap<u16, Vehicle>,
+
+    /// Keeps the connection counted towards the limit of its IP address until the client is dropped
+    _connection_slot: ConnectionSlot,
 }
 
 impl Client {
@@ -60,6 +132,8 @@ impl Client {
         udp_addr: SocketAddr,
         steam_id: u64,
         user: User,
+        map: String,
+        connection_slot: ConnectionSlot,
     ) -> Self {
         Self {
             tcp_conn,
@@ -70,10 +144,46 @@ impl Client {
 
             synced: false,
 
+            map,
+            map_load: None,
+
+            chat_rate_limit: ChatRateLimit::default(),
+
             vehicles: HashMap::new(),
+
+            _connection_slot: connection_slot,
         }
     }
 
+    /// Tells the client to load a map. The client has until `deadline` to confirm it is done loading.
+    async fn load_map(&mut self, map: &str, deadline: Instant) -> anyhow::Result<()> {
+        let confirm_id = next_confirm_id();
+        self.tcp_conn
+            .write_packet(&Packet::LoadMap(
+                server_launcher::serverinfo::LoadMapPacket {
+                    confirm_id,
+                    map_name: map.to_string(),
+                },
+            ))
+            .await?;
+        self.map = map.to_string();
+        self.synced = false;
+        self.map_load = Some(PendingMapLoad {
+            confirm_id,
+            deadline,
+        });
+        Ok(())
+    }
+
+    /// Tells the client why it is being disconnected. The caller is responsible for dropping it.
+    async fn kick(&mut self, reason: String) -> anyhow::Result<()> {
+        self.tcp_conn
+            .write_packet(&Packet::PlayerKick(
+                server_launcher::generic::PlayerKickPacket { reason },
+            ))
+            .await
+    }
+
     async fn tcp_try_recv(&mut self) -> anyhow::Result<Option<Packet>> {
         self.tcp_conn.try_read_packet().await
     }
@@ -93,6 +203,19 @@ impl Client {
 struct ServerClients(HashMap<u64, Client>);
 
 impl ServerClients {
+    /// Finds a client by Steam ID or (case insensitive) player name.
+    fn find_client(&self, name_or_id: &str) -> Option<u64> {
+        if let Ok(steam_id) = name_or_id.parse::<u64>() {
+            if self.0.contains_key(&steam_id) {
+                return Some(steam_id);
+            }
+        }
+        self.0
+            .iter()
+            .find(|(_, client)| client.user.name.eq_ignore_ascii_case(name_or_id))
+            .map(|(steam_id, _)| *steam_id)
+    }
+
     fn get_client_from_udp_addr(&self, addr: SocketAddr) -> Option<&Client> {
         for (_, client) in &self.0 {
             if client.udp_addr == addr {
@@ -182,24 +305,67 @@ impl ServerUdp {
 }
 
 struct Server {
+    config: Config,
+    /// Shared with the client accept thread, so new clients load the right map
+    current_map: Arc<RwLock<String>>,
+    rotation: MapRotation,
+    permissions: Permissions,
+    /// Copy of `permissions` for the client accept thread, which checks reserved slots
+    shared_permissions: Arc<RwLock<Permissions>>,
+    /// Shared with the client accept thread, which turns away banned players
+    bans: Arc<RwLock<BanList>>,
+    /// Shared with the client accept thread and the admin API, which report it to others
+    player_count: Arc<AtomicUsize>,
+
     udp: ServerUdp,
     clients: ServerClients,
 
     plugins: LuaEnvironment,
 
+    last_tick: Instant,
+    last_plugin_check: Instant,
+    last_storage_flush: Instant,
+    broken_plugins: Vec<plugins::BrokenPlugin>,
+
     update_player_data_flag: bool,
+    running: bool,
 }
 
 impl Server {
-    fn new(udp_socket: UdpListener<Packet>) -> Self {
+    fn new(
+        udp_socket: UdpListener<Packet>,
+        config: Config,
+        current_map: Arc<RwLock<String>>,
+        bans: Arc<RwLock<BanList>>,
+        permissions: Permissions,
+        shared_permissions: Arc<RwLock<Permissions>>,
+        player_count: Arc<AtomicUsize>,
+    ) -> Self {
+        // TODO: Error handling here please :3
+        let plugins =
+            LuaEnvironment::new(config.plugins.clone()).expect("Failed to load Lua plugin system!");
+
         Self {
+            rotation: MapRotation::new(&config.map_rotation, &config.general.map),
+            permissions,
+            shared_permissions,
+            config,
+            current_map,
+            bans,
+            player_count,
+
             udp: ServerUdp(udp_socket),
             clients: ServerClients(HashMap::new()),
 
-            // TODO: Error handling here please :3
-            plugins: LuaEnvironment::new().expect("Failed to load Lua plugin system!"),
+            plugins,
+
+            last_tick: Instant::now(),
+            last_plugin_check: Instant::now(),
+            last_storage_flush: Instant::now(),
+            broken_plugins: Vec::new(),
 
             update_player_data_flag: false,
+            running: true,
         }
     }
 
@@ -207,6 +373,8 @@ impl Server {
         let tcp_packets = self.clients.tcp_gather_packets().await;
         let udp_packets = self.udp.udp_gather_packets().await;
 
+        self.sync_plugin_players().await;
+
         for (steam_id, packet) in tcp_packets {
             self.tcp_handle_packet(steam_id, packet).await;
         }
@@ -215,11 +383,25 @@ impl Server {
             self.udp_handle_packet(udp_addr, packet).await;
         }
 
+        let now = Instant::now();
+        let dt = now.duration_since(self.last_tick).as_secs_f64();
+        self.last_tick = now;
+        self.plugins.event_on_tick(dt).await;
+        self.plugins.run_timers().await;
+        self.plugins.run_http_callbacks().await;
+
+        self.handle_plugin_actions().await;
+        self.kick_map_load_timeouts().await;
+        self.update_map_rotation().await;
+        self.hot_reload_plugins().await;
+        self.flush_plugin_storage().await;
+
         // Update all vehicle positions and runtime data
         for (steam_id, client) in self.clients.0.iter() {
             for (veh_id, veh) in client.vehicles.iter() {
                 for (s2, c2) in self.clients.0.iter() {
-                    if s2 == steam_id {
+                    // Clients that are still loading a map have nothing to put these vehicles on
+                    if s2 == steam_id || c2.map_load.is_some() {
                         continue;
                     }
 
@@ -287,40 +469,58 @@ impl Server {
     async fn tcp_handle_packet(&mut self, steam_id: u64, packet: Packet) {
         match packet {
             Packet::VehicleSpawn(mut p) => {
-                let mut block_spawn = false;
-                if let Some(veh_id) = self.spawn_vehicle(steam_id, p.vehicle_data.clone()).await {
-                    if let Some(client) = self.clients.0.get_mut(&steam_id) {
-                        if let Err(e) = client
-                            .tcp_conn
-                            .write_packet(&Packet::VehicleConfirm(
-                                server_launcher::gameplay::VehicleConfirmPacket {
-                                    confirm_id: p.confirm_id,
-                                    vehicle_id: veh_id,
-                                    obj_id: p.vehicle_data.object_id,
-                                },
-                            ))
-                            .await
-                        {
-                            error!("{}", e);
-                            block_spawn = true;
-                            // TODO: Kick client because error
-                        }
-                    } else {
-                        block_spawn = true;
-                    }
-                    if !block_spawn {
-                        trace!("spawning vehicle ({veh_id})");
-                        p.vehicle_id = veh_id;
-                        self.clients
-                            .tcp_broadcast_packet(Packet::VehicleSpawn(p), Some(steam_id))
-                            .await;
-                    }
+                let Some(veh_id) = self.spawn_vehicle(steam_id, p.vehicle_data.clone()).await
+                else {
+                    // The client used up every vehicle ID
+                    self.kick_client(steam_id, String::from("Too many vehicles"))
+                        .await;
+                    return;
+                };
+
+                // Plugins get a say before the owner is told the spawn went through
+                let allowed = self.allow_vehicle_spawn(steam_id, veh_id).await;
+                let Some(client) = self.clients.0.get_mut(&steam_id) else {
+                    return;
+                };
+                if let Err(e) = client
+                    .tcp_conn
+                    .write_packet(&Packet::VehicleConfirm(
+                        server_launcher::gameplay::VehicleConfirmPacket {
+                            confirm_id: p.confirm_id,
+                            vehicle_id: veh_id,
+                            obj_id: p.vehicle_data.object_id,
+                        },
+                    ))
+                    .await
+                {
+                    // Without the confirmation the owner can't match up the vehicle ID, so
+                    // their vehicles would be out of sync with everyone else from here on
+                    error!("{}", e);
+                    self.kick_client(steam_id, String::from("Failed to confirm vehicle spawn"))
+                        .await;
+                    return;
+                }
+
+                if !allowed {
+                    debug!("Vehicle spawn ({steam_id}, {veh_id}) cancelled by plugin");
+                    // The owner needs the confirmation to know which vehicle to delete
+                    self.cancel_vehicle_spawn(steam_id, veh_id).await;
                 } else {
-                    block_spawn = true;
+                    trace!("spawning vehicle ({veh_id})");
+                    p.vehicle_id = veh_id;
+                    self.clients
+                        .tcp_broadcast_packet(Packet::VehicleSpawn(p), Some(steam_id))
+                        .await;
                 }
-                if block_spawn {
-                    error!("block spawn?");
-                    todo!();
+            }
+            Packet::Confirmation(p) => self.confirm_map_load(steam_id, p.confirm_id),
+            Packet::ChatMessage(p) => self.handle_chat_message(steam_id, p.message).await,
+            Packet::VehicleDelete(p) => {
+                // You can only delete your own vehicles!
+                if p.player_id == steam_id {
+                    // The owner already deleted it on their end
+                    self.delete_vehicle(steam_id, p.vehicle_id, Some(steam_id))
+                        .await;
                 }
             }
             _ => error!("Unsupported packet (TCP): {:?}", packet),
@@ -340,9 +540,15 @@ impl Server {
                 if p.player_id == player_id {
                     if let Ok(parsed) = serde_json::from_str::<VehicleTransformData>(&p.transform) {
                         let client = self.clients.get_mut_client_from_udp_addr(addr).unwrap();
-                        if let Some(veh) = client.vehicles.get(&p.vehicle_id) {
+                        if let Some(veh) = client.vehicles.get_mut(&p.vehicle_id) {
+                            if veh
+                                .teleported_until
+                                .is_some_and(|until| Instant::now() < until)
+                            {
+                                return;
+                            }
+                            veh.teleported_until = None;
                             if parsed.ms > veh.latest_transform.ms {
-                                let veh = client.vehicles.get_mut(&p.vehicle_id).unwrap();
                                 veh.latest_transform = parsed;
                             }
                         }
@@ -366,16 +572,226 @@ impl Server {
         }
     }
 
-    async fn add_client(&mut self, client: Client) {
+    async fn add_client(&mut self, mut client: Client) {
         trace!("Client arrived at server");
         self.update_player_data_flag = true;
 
         let steam_id = client.steam_id.clone();
         let name = client.user.name.clone();
 
+        // The player may have been banned while they were still connecting
+        let check = self.bans.read().await.check(steam_id);
+        if let Err(reason) = check {
+            info!("Refusing {} ({}): {}", name, steam_id, reason);
+            if let Err(e) = client.kick(reason).await {
+                error!("{}", e);
+            }
+            return;
+        }
+
+        if !self.has_free_slot(steam_id).await {
+            info!("Refusing {} ({}): server is full", name, steam_id);
+            if let Err(e) = client.kick(String::from("Server is full")).await {
+                error!("{}", e);
+            }
+            return;
+        }
+
+        if let Err(reason) = self
+            .plugins
+            .event_on_player_connecting(steam_id, &name)
+            .await
+        {
+            let reason =
+                reason.unwrap_or_else(|| String::from("You are not allowed to join this server"));
+            info!(
+                "{} ({}) was rejected by a plugin: {}",
+                name, steam_id, reason
+            );
+            if let Err(e) = client.kick(reason).await {
+                error!("{}", e);
+            }
+            return;
+        }
+        self.plugins.event_on_player_auth(steam_id, &name).await;
+
+        // The map may have changed while this client was still connecting
+        let current_map = self.current_map.read().await.clone();
+        if client.map != current_map {
+            let deadline =
+                Instant::now() + Duration::from_secs(self.config.general.map_load_timeout);
+            if let Err(e) = client.load_map(&current_map, deadline).await {
+                error!("{}", e);
+                return;
+            }
+        } else {
+            // The client already confirmed loading the map while connecting
+            client.synced = true;
+        }
+
         self.clients.0.insert(client.steam_id, client);
+        self.sync_plugin_players().await;
+
+        self.plugins.event_on_player_join(steam_id, &name).await;
+    }
+
+    /// Whether a player can join without going over `max_players`. The reserved slots are
+    /// kept for whitelisted players and players with the `slots.reserved` permission.
+    async fn has_free_slot(&self, steam_id: u64) -> bool {
+        // A player that is reconnecting replaces their old connection
+        let online = self.clients.0.keys().filter(|id| **id != steam_id).count();
+        let reserved = self.bans.read().await.is_whitelisted(steam_id)
+            || self.has_permission(steam_id, RESERVED_SLOT_PERMISSION);
+        slot_available(&self.config.general, online, reserved)
+    }
+
+    /// Sends a chat message from the server to every client.
+    async fn broadcast_server_message(&mut self, message: String) {
+        info!("[Server] {}", message);
+        self.clients
+            .tcp_broadcast_packet(
+                Packet::ChatMessage(server_launcher::gameplay::ChatMessagePacket {
+                    player_id: 0,
+                    message,
+                }),
+                None,
+            )
+            .await;
+    }
+
+    /// Sends the client a kick packet with the given reason and removes them from the server.
+    async fn kick_client(&mut self, steam_id: u64, reason: String) {
+        if let Some(mut client) = self.clients.0.remove(&steam_id) {
+            info!("Kicking {} ({}): {}", client.user.name, steam_id, reason);
+            if let Err(e) = client.kick(reason).await {
+                error!("{}", e);
+            }
+            self.update_player_data_flag = true;
+        }
+    }
+
+    /// Updates the player and vehicle snapshots plugins get to see, and the shared player count.
+    /// This is also where plugins find out about players that disconnected, no matter how.
+    async fn sync_plugin_players(&self) {
+        self.player_count
+            .store(self.clients.0.len(), Ordering::Relaxed);
+
+        let players = self
+            .clients
+            .0
+            .values()
+            .map(|client| PlayerInfo {
+                steam_id: client.steam_id,
+                name: client.user.name.clone(),
+                avatar_hash: client.user.avatar_hash.clone(),
+                synced: client.synced,
+                vehicles: client.vehicles.keys().copied().collect(),
+            })
+            .collect();
+        for player in self.plugins.set_players(players).await {
+            self.plugins
+                .event_on_player_disconnect(player.steam_id, &player.name)
+                .await;
+        }
+
+        let vehicles = self
+            .clients
+            .0
+            .values()
+            .flat_map(|client| {
+                client.vehicles.iter().map(|(vehicle_id, veh)| VehicleInfo {
+                    owner: client.steam_id,
+                    vehicle_id: *vehicle_id,
+                    data: veh.data_json.clone(),
+                    pos: veh.latest_transform.pos,
+                    rot: veh.latest_transform.rot,
+                    vel: veh.latest_transform.vel,
+                    rvel: veh.latest_transform.rvel,
+                })
+            })
+            .collect();
+        self.plugins.set_vehicles(vehicles).await;
+    }
+
+    /// Carries out everything plugins asked for since the last time this was called.
+    async fn handle_plugin_actions(&mut self) {
+        // A plugin can be reported more than once before it gets disabled
+        let mut disabled = HashSet::new();
+        for action in self.plugins.take_actions().await {
+            match action {
+                ApiAction::ChangeMap(map) => {
+                    if let Err(e) = self.change_map(map).await {
+                        warn!("Plugin map change failed: {}", e);
+                    }
+                }
+                ApiAction::VoteMap { steam_id, map } => {
+                    if let Err(e) = self.vote_map(steam_id, &map).await {
+                        warn!("Plugin map vote failed: {}", e);
+                    }
+                }
+                ApiAction::KickPlayer { steam_id, reason } => {
+                    self.kick_client(steam_id, reason).await
+                }
+                ApiAction::SendMessage {
+                    steam_id: Some(steam_id),
+                    message,
+                } => self.send_server_message(steam_id, message).await,
+                ApiAction::SendMessage {
+                    steam_id: None,
+                    message,
+                } => self.broadcast_server_message(message).await,
+                ApiAction::DeleteVehicle {
+                    steam_id,
+                    vehicle_id,
+                } => {
+                    if !self.delete_vehicle(steam_id, vehicle_id, None).await {
+                        warn!("Plugin tried to delete unknown vehicle ({steam_id}, {vehicle_id})");
+                    }
+                }
+                ApiAction::TeleportVehicle {
+                    steam_id,
+                    vehicle_id,
+                    pos,
+                    rot,
+                } => {
+                    if !self.teleport_vehicle(steam_id, vehicle_id, pos, rot).await {
+                        warn!("Plugin tried to move unknown vehicle ({steam_id}, {vehicle_id})");
+                    }
+                }
+                ApiAction::ChangePermissions { steam_id, change } => {
+                    self.change_permissions(steam_id, &change).await;
+                }
+                ApiAction::ChangeBans(change) => {
+                    self.change_bans(&change).await;
+                }
+                ApiAction::DisablePlugin(name) => {
+                    if !disabled.insert(name.clone()) {
+                        continue;
+                    }
+                    if let Err(e) = self.plugins.unload_plugin(&name).await {
+                        error!("Failed to disable plugin {}: {}", name, e);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Lets plugins know the server is going down and kicks every client with a proper reason,
+    /// so they don't just see the connection drop.
+    async fn shutdown(&mut self) {
+        info!("Shutting down server...");
 
-        self.plugins.event_on_player_auth(steam_id, name).await;
+        self.plugins.event_on_server_shutdown().await;
+
+        let steam_ids = self.clients.0.keys().copied().collect::<Vec<_>>();
+        for steam_id in steam_ids {
+            self.kick_client(steam_id, String::from("Server shutting down"))
+                .await;
+        }
+        // Plugins hear about the kicked players before they are unloaded
+        self.sync_plugin_players().await;
+
+        self.unload_plugins().await;
     }
 
     /// Returns None if it failed to spawn a vehicle
@@ -387,31 +803,35 @@ impl Server {
     }
 }
 
-pub async fn server_main(mut rx: mpsc::Receiver<Client>, udp_listener: UdpListener<Packet>) {
+pub async fn server_main(
+    mut rx: mpsc::Receiver<Client>,
+    mut cmd_rx: mpsc::Receiver<ServerCommand>,
+    udp_listener: UdpListener<Packet>,
+    config: Config,
+    current_map: Arc<RwLock<String>>,
+    bans: Arc<RwLock<BanList>>,
+    permissions: Permissions,
+    shared_permissions: Arc<RwLock<Permissions>>,
+    player_count: Arc<AtomicUsize>,
+) {
     let mut interval = tokio::time::interval(tokio::time::Duration::from_millis(20)); // 20ms = 50 ticks per second
     interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
 
-    let mut server = Server::new(udp_listener);
+    let mut server = Server::new(
+        udp_listener,
+        config,
+        current_map,
+        bans,
+        permissions,
+        shared_permissions,
+        player_count,
+    );
     info!("Server running!");
 
     // Load plugins
-    // TODO: Look inside plugins folder to discover plugins
-    if let Err(e) = server
-        .plugins
-        .load_plugin("broken".to_string(), "plugins/broken/main.lua")
-        .await
-    {
-        error!("uh oh {}", e);
-    }
-    if let Err(e) = server
-        .plugins
-        .load_plugin("example".to_string(), "plugins/example/main.lua")
-        .await
-    {
-        error!("uh oh {}", e);
-    }
+    server.load_plugins().await;
 
-    loop {
+    while server.running {
         match rx.try_recv() {
             Ok(client) => {
                 server.add_client(client).await;
@@ -423,18 +843,54 @@ pub async fn server_main(mut rx: mpsc::Receiver<Client>, udp_listener: UdpListen
             }
         }
 
+        while let Ok(command) = cmd_rx.try_recv() {
+            server.handle_command(command).await;
+        }
+
         // TODO: Measure ticks per second of this loop to make sure we are running
         //       at roughly 50tps
-        let mut need_tick = true;
-        tokio::select!(
-            _ = server.tick() => {},
-            _ = interval.tick() => {
-                trace!("INTERVAL CANCELLED SERVER TICK");
-                need_tick = false;
-            },
-        );
-        if need_tick {
-            interval.tick().await;
+        // The tick always runs to completion, cancelling it halfway would lose whatever it was
+        // in the middle of (plugin actions, timers, reloads). A slow tick delays the next one.
+        server.tick().await;
+        interval.tick().await;
+    }
+
+    server.shutdown().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn general(max_players: usize, reserved_slots: usize) -> ConfigGeneral {
+        ConfigGeneral {
+            map: String::new(),
+            map_load_timeout: 120,
+            max_players,
+            reserved_slots,
         }
     }
+
+    #[test]
+    fn no_player_limit_by_default() {
+        assert!(slot_available(&general(0, 0), 1000, false));
+        assert!(slot_available(&general(0, 5), 1000, false));
+    }
+
+    #[test]
+    fn reserved_slots_are_kept_for_reserved_players() {
+        let general = general(10, 2);
+        assert!(slot_available(&general, 7, false));
+        assert!(!slot_available(&general, 8, false));
+        assert!(slot_available(&general, 8, true));
+        assert!(slot_available(&general, 9, true));
+        assert!(!slot_available(&general, 10, true));
+    }
+
+    #[test]
+    fn more_reserved_slots_than_players_reserves_everything() {
+        let general = general(2, 5);
+        assert!(!slot_available(&general, 0, false));
+        assert!(slot_available(&general, 1, true));
+    }
 }
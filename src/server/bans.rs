@@ -0,0 +1,254 @@
+use std::fmt::Write;
+use std::net::IpAddr;
+use std::path::Path;
+use std::time::Duration;
+
+use super::Server;
+use crate::bans::{format_duration, parse_duration, unix_time, Ban, BanChange};
+
+impl Server {
+    /// Changes the ban list, saves it and lets plugins know.
+    /// Banned players (and everyone not whitelisted, when enabling the whitelist) that
+    /// are online get kicked. Returns false if nothing changed.
+    pub(super) async fn change_bans(&mut self, change: &BanChange) -> bool {
+        let bans = {
+            let mut bans = self.bans.write().await;
+            bans.remove_expired();
+            if !change.apply(&mut bans) {
+                return false;
+            }
+            info!("Ban list changed: {:?}", change);
+
+            let path = Path::new(&self.config.bans.file);
+            if let Err(e) = bans.save(path) {
+                error!("Failed to save {}: {}", path.display(), e);
+            }
+            bans.clone()
+        };
+
+        let to_kick = self
+            .clients
+            .0
+            .iter()
+            .filter_map(|(steam_id, client)| {
+                // The UDP address has the same IP as the TCP connection
+                bans.check(*steam_id)
+                    .and_then(|_| bans.check_ip(client.udp_addr.ip()))
+                    .err()
+                    .map(|reason| (*steam_id, reason))
+            })
+            .collect::<Vec<_>>();
+        self.plugins.set_bans(bans).await;
+        for (steam_id, reason) in to_kick {
+            self.kick_client(steam_id, reason).await;
+        }
+        true
+    }
+
+    pub(super) async fn cmd_ban(&mut self, args: &[String]) -> String {
+        let Some(steam_id) = args.first().and_then(|arg| self.resolve_player(arg)) else {
+            return String::from("Usage: ban <name|steamid> [duration] [reason]");
+        };
+
+        let (duration, reason) = match parse_ban_args(&args[1..]) {
+            Ok(parsed) => parsed,
+            Err(e) => return e,
+        };
+        self.change_bans(&BanChange::Ban {
+            steam_id,
+            reason,
+            duration,
+        })
+        .await;
+        ban_summary(&steam_id.to_string(), duration)
+    }
+
+    pub(super) async fn cmd_ban_ip(&mut self, args: &[String]) -> String {
+        let Some(ip) = args.first().and_then(|arg| arg.parse::<IpAddr>().ok()) else {
+            return String::from("Usage: banip <ip> [duration] [reason]");
+        };
+        let (duration, reason) = match parse_ban_args(&args[1..]) {
+            Ok(parsed) => parsed,
+            Err(e) => return e,
+        };
+        self.change_bans(&BanChange::BanIp {
+            ip,
+            reason,
+            duration,
+        })
+        .await;
+        ban_summary(&ip.to_string(), duration)
+    }
+
+    pub(super) async fn cmd_unban_ip(&mut self, args: &[String]) -> String {
+        let Some(ip) = args.first().and_then(|arg| arg.parse::<IpAddr>().ok()) else {
+            return String::from("Usage: unbanip <ip>");
+        };
+        if self.change_bans(&BanChange::UnbanIp(ip)).await {
+            format!("Unbanned {}", ip)
+        } else {
+            format!("{} is not banned", ip)
+        }
+    }
+
+    pub(super) async fn cmd_unban(&mut self, args: &[String]) -> String {
+        let Some(steam_id) = args.first().and_then(|arg| arg.parse::<u64>().ok()) else {
+            return String::from("Usage: unban <steamid>");
+        };
+        if self.change_bans(&BanChange::Unban(steam_id)).await {
+            format!("Unbanned {}", steam_id)
+        } else {
+            format!("{} is not banned", steam_id)
+        }
+    }
+
+    pub(super) async fn cmd_bans(&self) -> String {
+        let bans = self.bans.read().await;
+        let mut output = String::new();
+        write_bans(&mut output, "banned player(s)", &bans.bans);
+        output.push('\n');
+        write_bans(&mut output, "banned IP address(es)", &bans.ip_bans);
+        output
+    }
+
+    pub(super) async fn cmd_whitelist(&mut self, args: &[String]) -> String {
+        let change = match (args.first().map(String::as_str), args.get(1)) {
+            (None, _) => {
+                let bans = self.bans.read().await;
+                let state = if bans.whitelist.enabled {
+                    "enabled"
+                } else {
+                    "disabled"
+                };
+                let mut output = format!(
+                    "Whitelist is {}, {} player(s) whitelisted:",
+                    state,
+                    bans.whitelist.players.len()
+                );
+                for steam_id in &bans.whitelist.players {
+                    let _ = write!(output, "\n  {}", steam_id);
+                }
+                return output;
+            }
+            (Some("on"), None) => BanChange::EnableWhitelist(true),
+            (Some("off"), None) => BanChange::EnableWhitelist(false),
+            (Some(action @ ("add" | "remove")), Some(player)) => {
+                let Some(steam_id) = self.resolve_player(player) else {
+                    return format!("No player found matching `{}`", player);
+                };
+                BanChange::SetWhitelisted {
+                    steam_id,
+                    whitelisted: action == "add",
+                }
+            }
+            _ => return String::from("Usage: whitelist [on|off|add|remove <name|steamid>]"),
+        };
+
+        if self.change_bans(&change).await {
+            String::from("Updated whitelist")
+        } else {
+            String::from("Nothing changed")
+        }
+    }
+}
+
+/// Splits `[duration] [reason]`. The duration is optional, so anything that isn't one
+/// is part of the reason. A duration of zero is an error, instead of ending up as a
+/// permanent ban with "0" as the reason.
+fn parse_ban_args(args: &[String]) -> Result<(Option<Duration>, String), String> {
+    match args.split_first() {
+        Some((first, rest)) => match parse_duration(first) {
+            Some(duration) => Ok((Some(duration), rest.join(" "))),
+            None if is_zero_duration(first) => Err(String::from(
+                "A ban must last longer than 0, leave out the duration for a permanent ban",
+            )),
+            None => Ok((None, args.join(" "))),
+        },
+        None => Ok((None, String::new())),
+    }
+}
+
+/// Returns true for durations like `0` or `0m`, which `parse_duration` refuses.
+fn is_zero_duration(s: &str) -> bool {
+    s.trim_end_matches(|c: char| c.is_ascii_alphabetic())
+        .parse::<u64>()
+        == Ok(0)
+}
+
+fn ban_summary(target: &str, duration: Option<Duration>) -> String {
+    match duration {
+        Some(duration) => format!(
+            "Banned {} for {}",
+            target,
+            format_duration(duration.as_secs())
+        ),
+        None => format!("Banned {} permanently", target),
+    }
+}
+
+/// Lists the bans that are still active, under a `<count> <title>:` header.
+fn write_bans<'a>(
+    output: &mut String,
+    title: &str,
+    bans: impl IntoIterator<Item = (&'a String, &'a Ban)>,
+) {
+    let now = unix_time();
+    let active = bans
+        .into_iter()
+        .filter(|(_, ban)| !ban.is_expired(now))
+        .collect::<Vec<_>>();
+
+    let _ = write!(output, "{} {}:", active.len(), title);
+    for (target, ban) in active {
+        let _ = write!(output, "\n  {}", target);
+        if !ban.reason.is_empty() {
+            let _ = write!(output, ": {}", ban.reason);
+        }
+        match ban.expires {
+            Some(expires) => {
+                let _ = write!(
+                    output,
+                    " (expires in {})",
+                    format_duration(expires.saturating_sub(now))
+                );
+            }
+            None => output.push_str(" (permanent)"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn the_duration_is_optional() {
+        assert_eq!(
+            parse_ban_args(&args("30m being rude")),
+            Ok((
+                Some(Duration::from_secs(30 * 60)),
+                String::from("being rude")
+            ))
+        );
+        assert_eq!(
+            parse_ban_args(&args("being rude")),
+            Ok((None, String::from("being rude")))
+        );
+        assert_eq!(parse_ban_args(&[]), Ok((None, String::new())));
+    }
+
+    #[test]
+    fn zero_durations_are_refused() {
+        for line in ["0", "0m cheating", "00d"] {
+            assert!(
+                parse_ban_args(&args(line)).is_err(),
+                "{} was accepted",
+                line
+            );
+        }
+    }
+}
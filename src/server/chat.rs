@@ -0,0 +1,346 @@
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+use ngmp_protocol_impl::server_launcher;
+use ngmp_protocol_impl::server_launcher::Packet;
+
+use super::commands::{is_builtin_command, BUILTIN_COMMANDS};
+use super::Server;
+use crate::config::ConfigChat;
+
+/// Usage, help and the permission needed to use it
+const CHAT_COMMANDS: &[(&str, &str, Option<&str>)] = &[
+    ("/help", "Lists all commands you can use", None),
+    (
+        "/msg <name|steamid> <message>",
+        "Sends a private message to a player",
+        Some("chat.msg"),
+    ),
+    (
+        "/votemap <map>",
+        "Votes for the next map in the rotation",
+        Some("map.vote"),
+    ),
+];
+
+/// Keeps track of how many messages a client sent recently.
+#[derive(Default)]
+pub struct ChatRateLimit(VecDeque<Instant>);
+
+impl ChatRateLimit {
+    /// Returns false if the client already sent too many messages in the configured window.
+    /// A limit of 0 disables it.
+    fn allow(&mut self, config: &ConfigChat) -> bool {
+        if config.rate_limit_messages == 0 {
+            return true;
+        }
+        let now = Instant::now();
+        let window = Duration::from_secs(config.rate_limit_seconds);
+        while self
+            .0
+            .front()
+            .is_some_and(|sent| now.duration_since(*sent) > window)
+        {
+            self.0.pop_front();
+        }
+
+        if self.0.len() >= config.rate_limit_messages {
+            return false;
+        }
+        self.0.push_back(now);
+        true
+    }
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Replaces every filtered word in the message with asterisks, ignoring (ASCII) case.
+/// Only whole words are filtered, so filtering "ass" leaves "class" alone.
+pub fn filter_message(message: &str, filtered_words: &[String]) -> String {
+    let mut result = message.to_string();
+    for word in filtered_words.iter().filter(|w| !w.is_empty()) {
+        let word = word.to_ascii_lowercase();
+        // ASCII lowercasing keeps byte offsets the same, so positions carry over to `result`
+        let lowered = result.to_ascii_lowercase();
+        let mut masked = String::with_capacity(result.len());
+        let mut last = 0;
+        for (start, _) in lowered.match_indices(&word) {
+            let end = start + word.len();
+            let starts_word = !lowered[..start]
+                .chars()
+                .next_back()
+                .is_some_and(is_word_char);
+            let ends_word = !lowered[end..].chars().next().is_some_and(is_word_char);
+            if !starts_word || !ends_word {
+                continue;
+            }
+            masked.push_str(&result[last..start]);
+            masked.push_str(&"*".repeat(result[start..end].chars().count()));
+            last = end;
+        }
+        masked.push_str(&result[last..]);
+        result = masked;
+    }
+    result
+}
+
+impl Server {
+    /// Handles a chat message sent by a client.
+    pub(super) async fn handle_chat_message(&mut self, steam_id: u64, message: String) {
+        let message = message.trim();
+        if message.is_empty() {
+            return;
+        }
+
+        let Some(client) = self.clients.0.get_mut(&steam_id) else {
+            return;
+        };
+        if !client.chat_rate_limit.allow(&self.config.chat) {
+            self.send_server_message(steam_id, String::from("You are sending messages too fast!"))
+                .await;
+            return;
+        }
+        let name = client.user.name.clone();
+
+        let message = message
+            .chars()
+            .take(self.config.chat.max_length)
+            .collect::<String>();
+
+        if let Some(command) = message.strip_prefix('/') {
+            info!("[Chat] {} issued command: /{}", name, command);
+            self.handle_chat_command(steam_id, command).await;
+            return;
+        }
+
+        if !self.has_permission(steam_id, "chat.send") {
+            self.send_server_message(steam_id, String::from("You are not allowed to chat"))
+                .await;
+            return;
+        }
+
+        let Some(message) = self
+            .plugins
+            .event_on_chat_message(steam_id, &name, message)
+            .await
+        else {
+            debug!("[Chat] Message from {} cancelled by plugin", name);
+            return;
+        };
+        let message = filter_message(&message, &self.config.chat.filtered_words);
+
+        info!("[Chat] {}: {}", name, message);
+        self.clients
+            .tcp_broadcast_packet(
+                Packet::ChatMessage(server_launcher::gameplay::ChatMessagePacket {
+                    player_id: steam_id,
+                    message,
+                }),
+                None,
+            )
+            .await;
+    }
+
+    async fn handle_chat_command(&mut self, steam_id: u64, command: &str) {
+        let args = super::commands::parse_args(command);
+        let Some((name, args)) = args.split_first() else {
+            return;
+        };
+
+        let no_permission = || {
+            Some(String::from(
+                "You don't have permission to use this command",
+            ))
+        };
+        let reply = match name.as_str() {
+            "help" => Some(self.chat_cmd_help(steam_id).await),
+            "msg" | "w" if !self.has_permission(steam_id, "chat.msg") => no_permission(),
+            "msg" | "w" => self.chat_cmd_whisper(steam_id, args).await,
+            "votemap" if !self.has_permission(steam_id, "map.vote") => no_permission(),
+            "votemap" => match args.first() {
+                Some(map) => self.vote_map(steam_id, map).await.err(),
+                None => Some(String::from("Usage: /votemap <map>")),
+            },
+            // Console commands, for players that are allowed to use them
+            name if is_builtin_command(name) => {
+                if self.has_permission(steam_id, &format!("command.{}", name)) {
+                    Some(self.run_command(command).await)
+                } else {
+                    no_permission()
+                }
+            }
+            _ => Some(
+                self.run_plugin_command(name, Some(steam_id), args)
+                    .await
+                    .unwrap_or_else(|| {
+                        format!(
+                            "Unknown command /{}, type /help for a list of commands",
+                            name
+                        )
+                    }),
+            ),
+        };
+
+        if let Some(reply) = reply {
+            for line in reply.lines() {
+                self.send_server_message(steam_id, line.to_string()).await;
+            }
+        }
+    }
+
+    async fn chat_cmd_help(&self, steam_id: u64) -> String {
+        let mut output = String::from("Available commands:");
+        for (usage, help, permission) in CHAT_COMMANDS {
+            if self.can_run_command(steam_id, *permission) {
+                let _ = write!(output, "\n  {} - {}", usage, help);
+            }
+        }
+        for (usage, help) in BUILTIN_COMMANDS {
+            let name = usage.split_whitespace().next().unwrap_or_default();
+            if name != "help" && self.has_permission(steam_id, &format!("command.{}", name)) {
+                let _ = write!(output, "\n  /{} - {}", usage, help);
+            }
+        }
+        let commands = self.plugins.command_list().await;
+        Self::write_plugin_commands(
+            &mut output,
+            "/",
+            commands.iter().filter(|cmd| {
+                cmd.chat && self.can_run_command(steam_id, cmd.permission.as_deref())
+            }),
+        );
+        output
+    }
+
+    async fn chat_cmd_whisper(&mut self, steam_id: u64, args: &[String]) -> Option<String> {
+        if args.len() < 2 {
+            return Some(String::from("Usage: /msg <name|steamid> <message>"));
+        }
+        let Some(target) = self.clients.find_client(&args[0]) else {
+            return Some(format!("No player found matching `{}`", args[0]));
+        };
+
+        let message = format!(
+            "(whisper) {}",
+            filter_message(&args[1..].join(" "), &self.config.chat.filtered_words)
+        );
+        self.send_chat_to(target, steam_id, message.clone()).await;
+        // The sender gets a copy, unless they whispered to themselves
+        if target != steam_id {
+            self.send_chat_to(steam_id, steam_id, message).await;
+        }
+        None
+    }
+
+    /// Sends a chat message to a single client, as if it was sent by `sender_id`.
+    pub(super) async fn send_chat_to(&mut self, steam_id: u64, sender_id: u64, message: String) {
+        let Some(client) = self.clients.0.get_mut(&steam_id) else {
+            return;
+        };
+        if let Err(e) = client
+            .tcp_conn
+            .write_packet(&Packet::ChatMessage(
+                server_launcher::gameplay::ChatMessagePacket {
+                    player_id: sender_id,
+                    message,
+                },
+            ))
+            .await
+        {
+            error!("{}", e);
+            self.clients.0.remove(&steam_id);
+            self.update_player_data_flag = true;
+        }
+    }
+
+    /// Sends a chat message from the server to a single client.
+    pub(super) async fn send_server_message(&mut self, steam_id: u64, message: String) {
+        self.send_chat_to(steam_id, 0, message).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(words: &[&str]) -> Vec<String> {
+        words.iter().map(|w| w.to_string()).collect()
+    }
+
+    #[test]
+    fn masks_filtered_words_ignoring_case() {
+        let filtered = words(&["darn"]);
+        assert_eq!(
+            filter_message("Darn it, DARN!", &filtered),
+            "**** it, ****!"
+        );
+    }
+
+    #[test]
+    fn only_masks_whole_words() {
+        let filtered = words(&["ass"]);
+        assert_eq!(
+            filter_message("class assignment", &filtered),
+            "class assignment"
+        );
+        assert_eq!(
+            filter_message("ass, bass (ass)", &filtered),
+            "***, bass (***)"
+        );
+        assert_eq!(filter_message("ass_hat", &filtered), "ass_hat");
+    }
+
+    #[test]
+    fn masks_phrases_and_keeps_other_characters() {
+        let filtered = words(&["bad word", ""]);
+        assert_eq!(
+            filter_message("a bad word, größer", &filtered),
+            "a ********, größer"
+        );
+        assert_eq!(filter_message("nothing here", &[]), "nothing here");
+    }
+
+    #[test]
+    fn rate_limit_allows_a_burst_then_blocks() {
+        let config = ConfigChat {
+            rate_limit_messages: 2,
+            rate_limit_seconds: 60,
+            ..Default::default()
+        };
+        let mut limit = ChatRateLimit::default();
+        assert!(limit.allow(&config));
+        assert!(limit.allow(&config));
+        assert!(!limit.allow(&config));
+        // Blocked messages don't count towards the limit
+        assert_eq!(limit.0.len(), 2);
+    }
+
+    #[test]
+    fn rate_limit_forgets_old_messages() {
+        let config = ConfigChat {
+            rate_limit_messages: 1,
+            rate_limit_seconds: 0,
+            ..Default::default()
+        };
+        let mut limit = ChatRateLimit::default();
+        assert!(limit.allow(&config));
+        std::thread::sleep(Duration::from_millis(5));
+        assert!(limit.allow(&config));
+    }
+
+    #[test]
+    fn rate_limit_of_zero_allows_everything() {
+        let config = ConfigChat {
+            rate_limit_messages: 0,
+            rate_limit_seconds: 60,
+            ..Default::default()
+        };
+        let mut limit = ChatRateLimit::default();
+        for _ in 0..100 {
+            assert!(limit.allow(&config));
+        }
+    }
+}
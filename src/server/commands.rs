@@ -0,0 +1,400 @@
+use std::fmt::Write;
+
+use super::Server;
+use crate::console::ServerCommand;
+use crate::plugin::CommandInfo;
+
+pub const BUILTIN_COMMANDS: &[(&str, &str)] = &[
+    ("help", "Lists all available commands"),
+    ("list", "Lists all connected players"),
+    (
+        "kick <name|steamid> <reason>",
+        "Kicks a player from the server",
+    ),
+    ("say <message>", "Sends a chat message to all players"),
+    ("map [name]", "Shows the current map or changes it"),
+    (
+        "rotation [skip|vote <name|steamid> <map>]",
+        "Shows the map rotation, skips to the next map or votes for a player",
+    ),
+    (
+        "plugins [load|unload|reload] [name]",
+        "Lists, loads, unloads or reloads plugins",
+    ),
+    (
+        "perms <name|steamid> [addrole|removerole|grant|revoke <value>]",
+        "Shows or changes the roles and permissions of a player",
+    ),
+    ("perms reload", "Reads the permissions file again"),
+    ("roles", "Lists all roles and their permissions"),
+    (
+        "ban <name|steamid> [duration] [reason]",
+        "Bans a player, permanently unless a duration like 30m, 12h or 7d is given",
+    ),
+    ("unban <steamid>", "Lifts the ban of a player"),
+    (
+        "banip <ip> [duration] [reason]",
+        "Bans an IP address, refusing all connections from it",
+    ),
+    ("unbanip <ip>", "Lifts the ban of an IP address"),
+    ("bans", "Lists all banned players and IP addresses"),
+    (
+        "whitelist [on|off|add|remove <name|steamid>]",
+        "Shows or changes the whitelist",
+    ),
+    ("stop", "Stops the server"),
+];
+
+/// Returns true for commands built into the server, which players can run from chat
+/// if they have the `command.<name>` permission.
+pub fn is_builtin_command(name: &str) -> bool {
+    BUILTIN_COMMANDS
+        .iter()
+        .any(|(usage, _)| usage.split_whitespace().next() == Some(name))
+}
+
+/// Splits a command line into arguments, keeping "quoted strings" together.
+pub fn parse_args(line: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut current = String::new();
+    let mut in_quotes = false;
+    let mut has_arg = false;
+
+    for c in line.chars() {
+        match c {
+            '"' => {
+                in_quotes = !in_quotes;
+                has_arg = true;
+            }
+            c if c.is_whitespace() && !in_quotes => {
+                if has_arg {
+                    args.push(std::mem::take(&mut current));
+                    has_arg = false;
+                }
+            }
+            c => {
+                current.push(c);
+                has_arg = true;
+            }
+        }
+    }
+    if has_arg {
+        args.push(current);
+    }
+
+    args
+}
+
+impl Server {
+    pub(super) async fn handle_command(&mut self, command: ServerCommand) {
+        let output = match &command.role {
+            Some(role) if !self.role_may_run(role, &command.line).await => {
+                String::from("You don't have permission to use this command")
+            }
+            _ => self.run_command(&command.line).await,
+        };
+        match command.reply {
+            Some(reply) => {
+                // The other side may have given up waiting, that's fine
+                let _ = reply.send(output);
+            }
+            None => {
+                for line in output.lines() {
+                    info!("{}", line);
+                }
+            }
+        }
+    }
+
+    /// Whether a role has permission to run a command line. Like in chat, built-in commands
+    /// need `command.<name>` and plugin commands the permission they were registered with.
+    async fn role_may_run(&self, role: &str, line: &str) -> bool {
+        let Some(name) = parse_args(line).into_iter().next() else {
+            return true;
+        };
+        if name == "help" {
+            return true;
+        }
+        if is_builtin_command(&name) {
+            return self
+                .permissions
+                .role_has_permission(role, &format!("command.{}", name));
+        }
+        match self.plugins.find_command(&name).await {
+            Some(cmd) => cmd.permission.map_or(true, |node| {
+                self.permissions.role_has_permission(role, &node)
+            }),
+            // Unknown commands only produce an error message
+            None => true,
+        }
+    }
+
+    /// Runs a single command line and returns its output.
+    pub(super) async fn run_command(&mut self, line: &str) -> String {
+        let args = parse_args(line);
+        let Some((name, args)) = args.split_first() else {
+            return String::new();
+        };
+
+        match name.as_str() {
+            "help" => self.cmd_help().await,
+            "list" => self.cmd_list(),
+            "kick" => self.cmd_kick(args).await,
+            "say" => self.cmd_say(args).await,
+            "map" => self.cmd_map(args).await,
+            "rotation" => self.cmd_rotation(args).await,
+            "plugins" => self.cmd_plugins(args).await,
+            "perms" => self.cmd_perms(args).await,
+            "roles" => self.cmd_roles(),
+            "ban" => self.cmd_ban(args).await,
+            "unban" => self.cmd_unban(args).await,
+            "banip" => self.cmd_ban_ip(args).await,
+            "unbanip" => self.cmd_unban_ip(args).await,
+            "bans" => self.cmd_bans().await,
+            "whitelist" => self.cmd_whitelist(args).await,
+            "stop" => {
+                self.running = false;
+                String::from("Stopping server...")
+            }
+            _ => self
+                .run_plugin_command(name, None, args)
+                .await
+                .unwrap_or_else(|| {
+                    format!(
+                        "Unknown command `{}`, type `help` for a list of commands",
+                        name
+                    )
+                }),
+        }
+    }
+
+    /// Runs a plugin command for the console (`caller` is None) or a player in chat.
+    /// Returns None if there is no such command available to the caller.
+    pub(super) async fn run_plugin_command(
+        &mut self,
+        name: &str,
+        caller: Option<u64>,
+        args: &[String],
+    ) -> Option<String> {
+        let cmd = self.plugins.find_command(name).await?;
+        let caller = match caller {
+            Some(steam_id) => {
+                if !cmd.chat {
+                    return None;
+                }
+                if !self.can_run_command(steam_id, cmd.permission.as_deref()) {
+                    return Some(String::from(
+                        "You don't have permission to use this command",
+                    ));
+                }
+                let player_name = self.clients.0.get(&steam_id)?.user.name.clone();
+                Some((steam_id, player_name))
+            }
+            None if !cmd.console => return None,
+            None => None,
+        };
+
+        let res = self
+            .plugins
+            .call_command(
+                name,
+                caller.as_ref().map(|(id, name)| (*id, name.as_str())),
+                args.to_vec(),
+            )
+            .await?;
+        Some(match res {
+            Ok(output) => output.unwrap_or_default(),
+            Err(e) => format!("Command `{}` failed: {}", name, e),
+        })
+    }
+
+    /// Whether a player may run a command that needs `permission`.
+    /// The console can run everything.
+    pub(super) fn can_run_command(&self, steam_id: u64, permission: Option<&str>) -> bool {
+        permission.map_or(true, |node| self.has_permission(steam_id, node))
+    }
+
+    /// Appends plugin commands to a help listing, grouped by plugin.
+    pub(super) fn write_plugin_commands<'a>(
+        output: &mut String,
+        prefix: &str,
+        commands: impl Iterator<Item = &'a CommandInfo>,
+    ) {
+        let mut current_plugin = None;
+        for cmd in commands {
+            if current_plugin != Some(&cmd.plugin) {
+                let _ = write!(output, "\nPlugin {}:", cmd.plugin);
+                current_plugin = Some(&cmd.plugin);
+            }
+            let _ = write!(output, "\n  {}{}", prefix, cmd.name);
+            if !cmd.usage.is_empty() {
+                let _ = write!(output, " {}", cmd.usage);
+            }
+            let _ = write!(output, " - {}", cmd.help);
+        }
+    }
+
+    async fn cmd_help(&self) -> String {
+        let mut output = String::from("Available commands:");
+        for (usage, help) in BUILTIN_COMMANDS {
+            let _ = write!(output, "\n  {} - {}", usage, help);
+        }
+        let commands = self.plugins.command_list().await;
+        Self::write_plugin_commands(&mut output, "", commands.iter().filter(|cmd| cmd.console));
+        output
+    }
+
+    fn cmd_list(&self) -> String {
+        let mut output = match self.config.general.max_players {
+            0 => format!("{} player(s) online:", self.clients.0.len()),
+            max => format!("{}/{} player(s) online:", self.clients.0.len(), max),
+        };
+        for (steam_id, client) in &self.clients.0 {
+            let _ = write!(
+                output,
+                "\n  {} ({}) - {} vehicle(s)",
+                client.user.name,
+                steam_id,
+                client.vehicles.len()
+            );
+        }
+        output
+    }
+
+    async fn cmd_kick(&mut self, args: &[String]) -> String {
+        let Some(target) = args.first() else {
+            return String::from("Usage: kick <name|steamid> <reason>");
+        };
+        let Some(steam_id) = self.clients.find_client(target) else {
+            return format!("No player found matching `{}`", target);
+        };
+
+        let reason = if args.len() > 1 {
+            args[1..].join(" ")
+        } else {
+            String::from("Kicked by server")
+        };
+        self.kick_client(steam_id, reason.clone()).await;
+        format!("Kicked {} ({})", steam_id, reason)
+    }
+
+    async fn cmd_say(&mut self, args: &[String]) -> String {
+        if args.is_empty() {
+            return String::from("Usage: say <message>");
+        }
+        self.broadcast_server_message(args.join(" ")).await;
+        String::new()
+    }
+
+    async fn cmd_map(&mut self, args: &[String]) -> String {
+        match args.first() {
+            Some(map) => match self.change_map(map.clone()).await {
+                Ok(map) => format!("Changed map to {}", map),
+                Err(e) => e,
+            },
+            None => format!("Current map: {}", self.current_map.read().await),
+        }
+    }
+
+    async fn cmd_rotation(&mut self, args: &[String]) -> String {
+        match args.first().map(|s| s.as_str()) {
+            None => self.map_rotation_status(),
+            Some("skip") => match self.skip_map_rotation().await {
+                Ok(map) => format!("Skipped to {}", map),
+                Err(e) => e,
+            },
+            Some("vote") => {
+                let (Some(target), Some(map)) = (args.get(1), args.get(2)) else {
+                    return String::from("Usage: rotation vote <name|steamid> <map>");
+                };
+                let Some(steam_id) = self.clients.find_client(target) else {
+                    return format!("No player found matching `{}`", target);
+                };
+                match self.vote_map(steam_id, map).await {
+                    Ok(map) => format!("Voted for {}", map),
+                    Err(e) => e,
+                }
+            }
+            Some(other) => format!(
+                "Unknown subcommand `{}`, usage: rotation [skip|vote <name|steamid> <map>]",
+                other
+            ),
+        }
+    }
+
+    async fn cmd_plugins(&mut self, args: &[String]) -> String {
+        let name = args.get(1);
+        match (args.first().map(|s| s.as_str()), name) {
+            (None, _) => {
+                let plugins = self.plugins.loaded_plugins().await;
+                let mut output = format!("{} plugin(s) loaded:", plugins.len());
+                for plugin in plugins {
+                    let _ = write!(output, "\n  {} v{}", plugin.name, plugin.version);
+                    if plugin.errors > 0 {
+                        let _ = write!(output, " ({} error(s))", plugin.errors);
+                    }
+                }
+                output
+            }
+            (Some("reload"), None) => {
+                self.reload_plugins().await;
+                String::from("Reloaded all plugins")
+            }
+            (Some("reload"), Some(name)) => match self.plugins.reload_plugin(name).await {
+                Ok(()) => format!("Reloaded plugin {}", name),
+                Err(e) => format!("Failed to reload plugin {}: {}", name, e),
+            },
+            (Some("unload"), Some(name)) => match self.plugins.unload_plugin(name).await {
+                Ok(_) => format!("Unloaded plugin {}", name),
+                Err(e) => format!("Failed to unload plugin {}: {}", name, e),
+            },
+            (Some("load"), Some(name)) => match self.load_plugin_by_name(name).await {
+                Ok(()) => format!("Loaded plugin {}", name),
+                Err(e) => format!("Failed to load plugin {}: {}", name, e),
+            },
+            _ => String::from("Usage: plugins [load|unload|reload] [name]"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_on_whitespace() {
+        assert_eq!(
+            parse_args("kick  bob\tspamming "),
+            ["kick", "bob", "spamming"]
+        );
+        assert!(parse_args("   ").is_empty());
+        assert!(parse_args("").is_empty());
+    }
+
+    #[test]
+    fn keeps_quoted_strings_together() {
+        assert_eq!(
+            parse_args(r#"kick "Some Player" "no reason""#),
+            ["kick", "Some Player", "no reason"]
+        );
+        assert_eq!(parse_args(r#"say a"b c"d"#), ["say", "ab cd"]);
+    }
+
+    #[test]
+    fn keeps_empty_quoted_arguments() {
+        assert_eq!(parse_args(r#"kick "" reason"#), ["kick", "", "reason"]);
+    }
+
+    #[test]
+    fn unterminated_quote_runs_to_the_end() {
+        assert_eq!(parse_args(r#"say "hello world"#), ["say", "hello world"]);
+    }
+
+    #[test]
+    fn builtin_commands_are_recognized_by_name() {
+        assert!(is_builtin_command("kick"));
+        assert!(is_builtin_command("stop"));
+        assert!(!is_builtin_command("kick <name|steamid> <reason>"));
+        assert!(!is_builtin_command("hello"));
+    }
+}
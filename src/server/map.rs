@@ -0,0 +1,133 @@
+use std::time::{Duration, Instant};
+
+use super::Server;
+
+/// Turns a short map name like `gridmap_v2` into the full path the game expects.
+/// Full paths are passed through untouched, as long as they point into `/levels/`.
+pub fn normalize_map_name(map: &str) -> Result<String, String> {
+    let map = map.trim();
+    if map.starts_with('/') {
+        let valid = map.starts_with("/levels/")
+            && !map.split('/').any(|part| part == "..")
+            && !map.chars().any(|c| c.is_whitespace() || c.is_control());
+        if valid {
+            Ok(map.to_string())
+        } else {
+            Err(format!("Invalid map path `{}`", map))
+        }
+    } else if !map.is_empty()
+        && map
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
+        Ok(format!("/levels/{}/info.json", map))
+    } else {
+        Err(format!("Invalid map name `{}`", map))
+    }
+}
+
+impl Server {
+    /// Switches every connected client over to a new map.
+    /// All vehicles are cleared, as they would no longer exist on the new map anyway.
+    /// Returns the full map name, or why the map name is invalid.
+    pub(super) async fn change_map(&mut self, map: String) -> Result<String, String> {
+        let map = normalize_map_name(&map)?;
+        info!("Changing map to {}", map);
+        self.rotation.advance(&map);
+        self.plugins.event_on_map_changing(&map).await;
+
+        *self.current_map.write().await = map.clone();
+        self.plugins.set_current_map(map.clone()).await;
+
+        let deadline = Instant::now() + Duration::from_secs(self.config.general.map_load_timeout);
+        let mut to_remove = Vec::new();
+        for (steam_id, client) in &mut self.clients.0 {
+            client.vehicles.clear();
+            if let Err(e) = client.load_map(&map, deadline).await {
+                error!("{}", e);
+                to_remove.push(*steam_id);
+            }
+        }
+
+        for steam_id in to_remove {
+            self.clients.0.remove(&steam_id);
+        }
+        self.update_player_data_flag = true;
+
+        self.plugins.event_on_map_changed(&map).await;
+        Ok(map)
+    }
+
+    /// Handles a confirmation packet from a client that is loading a map.
+    pub(super) fn confirm_map_load(&mut self, steam_id: u64, confirm_id: u16) {
+        let Some(client) = self.clients.0.get_mut(&steam_id) else {
+            return;
+        };
+        match &client.map_load {
+            Some(load) if load.confirm_id == confirm_id => {
+                debug!(
+                    "{} ({}) finished loading {}",
+                    client.user.name, steam_id, client.map
+                );
+                client.map_load = None;
+                client.synced = true;
+            }
+            Some(_) => warn!("Invalid map confirmation ID from {}", steam_id),
+            None => warn!("Unexpected confirmation packet from {}", steam_id),
+        }
+    }
+
+    /// Kicks all clients that did not manage to load the map before their deadline.
+    pub(super) async fn kick_map_load_timeouts(&mut self) {
+        let now = Instant::now();
+        let timed_out = self
+            .clients
+            .0
+            .iter()
+            .filter(|(_, client)| {
+                client
+                    .map_load
+                    .as_ref()
+                    .is_some_and(|load| load.deadline <= now)
+            })
+            .map(|(steam_id, _)| *steam_id)
+            .collect::<Vec<_>>();
+
+        for steam_id in timed_out {
+            self.kick_client(steam_id, String::from("Failed to load the map in time"))
+                .await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_short_names() {
+        assert_eq!(
+            normalize_map_name("gridmap_v2").unwrap(),
+            "/levels/gridmap_v2/info.json"
+        );
+        assert_eq!(
+            normalize_map_name("/levels/italy/info.json").unwrap(),
+            "/levels/italy/info.json"
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_names() {
+        for map in [
+            "",
+            "  ",
+            "grid map",
+            "../secret",
+            "a/b",
+            "/etc/passwd",
+            "/levels/../x",
+        ] {
+            assert!(normalize_map_name(map).is_err(), "{:?} was accepted", map);
+        }
+    }
+}
@@ -0,0 +1,128 @@
+use std::fmt::Write;
+use std::path::Path;
+
+use super::Server;
+use crate::permissions::{PermissionChange, Permissions};
+
+impl Server {
+    pub(super) fn has_permission(&self, steam_id: u64, node: &str) -> bool {
+        self.permissions.has_permission(steam_id, node)
+    }
+
+    /// Changes a player's permissions, saves them and lets plugins know.
+    /// Returns false if nothing changed.
+    pub(super) async fn change_permissions(
+        &mut self,
+        steam_id: u64,
+        change: &PermissionChange,
+    ) -> bool {
+        if !change.apply(&mut self.permissions, steam_id) {
+            return false;
+        }
+        info!("Permissions of {} changed: {:?}", steam_id, change);
+
+        let path = Path::new(&self.config.permissions.file);
+        if let Err(e) = self.permissions.save(path) {
+            error!("Failed to save {}: {}", path.display(), e);
+        }
+        self.share_permissions().await;
+        true
+    }
+
+    /// Hands the current permissions to everything that keeps its own copy.
+    async fn share_permissions(&self) {
+        *self.shared_permissions.write().await = self.permissions.clone();
+        self.plugins.set_permissions(self.permissions.clone()).await;
+    }
+
+    /// Reads the permissions file again, e.g. after editing it by hand.
+    /// If it still doesn't load, the current permissions stay in place.
+    pub(super) async fn reload_permissions(&mut self) -> String {
+        let path = Path::new(&self.config.permissions.file);
+        let permissions = match Permissions::load(path) {
+            Ok(permissions) => permissions,
+            Err(e) => {
+                return format!(
+                    "Failed to load {}, kept the current permissions: {}",
+                    path.display(),
+                    e
+                )
+            }
+        };
+        self.permissions = permissions;
+        self.share_permissions().await;
+        String::from("Reloaded permissions")
+    }
+
+    /// Finds a connected player by name or Steam ID, or takes any Steam ID for
+    /// players that are offline.
+    pub(super) fn resolve_player(&self, name_or_id: &str) -> Option<u64> {
+        self.clients
+            .find_client(name_or_id)
+            .or_else(|| name_or_id.parse::<u64>().ok())
+    }
+
+    pub(super) async fn cmd_perms(&mut self, args: &[String]) -> String {
+        if matches!(args, [action] if action == "reload") {
+            return self.reload_permissions().await;
+        }
+
+        let Some(steam_id) = args.first().and_then(|arg| self.resolve_player(arg)) else {
+            return String::from(
+                "Usage: perms reload | perms <name|steamid> [addrole|removerole|grant|revoke <value>]",
+            );
+        };
+
+        match (args.get(1), args.get(2)) {
+            (None, _) => {
+                let mut output = format!(
+                    "Roles of {}: {}",
+                    steam_id,
+                    self.permissions.roles_of(steam_id).join(", ")
+                );
+                if let Some(player) = self.permissions.players.get(&steam_id.to_string()) {
+                    if !player.permissions.is_empty() {
+                        let _ = write!(
+                            output,
+                            "\nExtra permissions: {}",
+                            player.permissions.join(", ")
+                        );
+                    }
+                }
+                output
+            }
+            (Some(action), Some(value)) => match PermissionChange::parse(action, value) {
+                Some(PermissionChange::AddRole(role))
+                    if !self.permissions.roles.contains_key(&role) =>
+                {
+                    format!(
+                        "There is no role named `{}`, type `roles` for a list of roles",
+                        role
+                    )
+                }
+                Some(change) => {
+                    if self.change_permissions(steam_id, &change).await {
+                        format!("Updated permissions of {}", steam_id)
+                    } else {
+                        String::from("Nothing changed")
+                    }
+                }
+                None => format!("Unknown action `{}`", action),
+            },
+            _ => String::from(
+                "Usage: perms reload | perms <name|steamid> [addrole|removerole|grant|revoke <value>]",
+            ),
+        }
+    }
+
+    pub(super) fn cmd_roles(&self) -> String {
+        let mut output = String::from("Roles:");
+        for (name, role) in &self.permissions.roles {
+            let _ = write!(output, "\n  {}: {}", name, role.permissions.join(", "));
+            if !role.inherits.is_empty() {
+                let _ = write!(output, " (inherits {})", role.inherits.join(", "));
+            }
+        }
+        output
+    }
+}
@@ -0,0 +1,169 @@
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime};
+
+use super::Server;
+use crate::plugin::{self, LuaEnvironment};
+
+/// How often plugin files are checked for changes when hot reloading is enabled
+const HOT_RELOAD_INTERVAL: Duration = Duration::from_secs(1);
+/// How often plugin storage is written to disk
+const STORAGE_FLUSH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// A plugin that failed to hot reload, which is tried again once its files change.
+pub struct BrokenPlugin {
+    dir: PathBuf,
+    modified: Option<SystemTime>,
+}
+
+impl Server {
+    pub(super) async fn load_plugins(&mut self) {
+        let current_map = self.current_map.read().await.clone();
+        self.plugins.set_current_map(current_map).await;
+        self.plugins.set_permissions(self.permissions.clone()).await;
+        let bans = self.bans.read().await.clone();
+        self.plugins.set_bans(bans).await;
+
+        let plugins =
+            plugin::resolve_load_order(plugin::discover_plugins(&self.config.plugins.directory));
+        let total = plugins.len();
+
+        // A plugin failing to load should never take down the rest, except for plugins
+        // that depend on it
+        let mut failed = HashSet::new();
+        for plugin in plugins {
+            let name = plugin.manifest.name.clone();
+            if let Some(dep) = plugin
+                .manifest
+                .dependencies
+                .iter()
+                .find(|dep| failed.contains(*dep))
+            {
+                error!(
+                    "Skipping plugin {}: dependency `{}` failed to load",
+                    name, dep
+                );
+                failed.insert(name);
+                continue;
+            }
+
+            let version = plugin.manifest.version.clone();
+            match self.plugins.load_plugin(plugin).await {
+                Ok(()) => info!("Loaded plugin {} v{}", name, version),
+                Err(e) => {
+                    if !e.is_reported() {
+                        error!("Failed to load plugin {}: {}", name, e);
+                    }
+                    failed.insert(name);
+                }
+            }
+        }
+
+        info!("Loaded {}/{} plugin(s)", total - failed.len(), total);
+    }
+
+    /// Unloads every plugin, throws away the whole Lua environment and loads all plugins from scratch.
+    pub(super) async fn reload_plugins(&mut self) {
+        info!("Reloading plugins...");
+        self.unload_plugins().await;
+        match LuaEnvironment::new(self.config.plugins.clone()) {
+            Ok(plugins) => self.plugins = plugins,
+            Err(e) => {
+                error!("Failed to recreate Lua plugin system: {}", e);
+                return;
+            }
+        }
+        self.load_plugins().await;
+    }
+
+    /// Unloads every plugin, in reverse load order.
+    pub(super) async fn unload_plugins(&mut self) {
+        for plugin in self.plugins.loaded_plugins().await.into_iter().rev() {
+            if let Err(e) = self.plugins.unload_plugin(&plugin.name).await {
+                error!("Failed to unload plugin {}: {}", plugin.name, e);
+            }
+        }
+    }
+
+    /// Loads a single plugin from the plugins directory by name.
+    pub(super) async fn load_plugin_by_name(&mut self, name: &str) -> Result<(), String> {
+        let plugin = plugin::discover_plugins(&self.config.plugins.directory)
+            .into_iter()
+            .find(|p| p.manifest.name == name)
+            .ok_or_else(|| format!("No plugin named `{}` found", name))?;
+        self.plugins
+            .load_plugin(plugin)
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    /// Periodically saves plugin storage, so not much is lost if the server crashes.
+    /// Storage is always saved when a plugin is unloaded, which includes shutting down.
+    pub(super) async fn flush_plugin_storage(&mut self) {
+        if self.last_storage_flush.elapsed() < STORAGE_FLUSH_INTERVAL {
+            return;
+        }
+        self.last_storage_flush = Instant::now();
+        self.plugins.flush_storage().await;
+    }
+
+    /// Reloads plugins whose files changed on disk.
+    pub(super) async fn hot_reload_plugins(&mut self) {
+        if !self.config.plugins.hot_reload || self.last_plugin_check.elapsed() < HOT_RELOAD_INTERVAL
+        {
+            return;
+        }
+        self.last_plugin_check = Instant::now();
+
+        for plugin in self.plugins.changed_plugins().await {
+            let name = &plugin.manifest.name;
+            info!("Plugin {} changed on disk, reloading...", name);
+            match self.plugins.reload_plugin(name).await {
+                Ok(()) => info!("Reloaded plugin {}", name),
+                Err(e) => {
+                    if !e.is_reported() {
+                        error!("Failed to reload plugin {}: {}", name, e);
+                    }
+                    // The previous version is usually still running and gets reloaded as soon
+                    // as its files change again
+                    if self.plugins.is_loaded(name).await {
+                        continue;
+                    }
+                    self.broken_plugins.push(BrokenPlugin {
+                        modified: plugin::last_modified(&plugin.dir),
+                        dir: plugin.dir,
+                    });
+                }
+            }
+        }
+
+        // Give plugins that failed to reload another shot once they are fixed
+        for mut broken in std::mem::take(&mut self.broken_plugins) {
+            let modified = plugin::last_modified(&broken.dir);
+            if modified == broken.modified {
+                self.broken_plugins.push(broken);
+                continue;
+            }
+            broken.modified = modified;
+
+            // None if the error was already logged
+            let res = match plugin::discover_plugin(&broken.dir) {
+                Ok(plugin) => self
+                    .plugins
+                    .load_plugin(plugin)
+                    .await
+                    .map_err(|e| (!e.is_reported()).then(|| e.to_string())),
+                Err(e) => Err(Some(e.to_string())),
+            };
+            match res {
+                Ok(()) => info!("Loaded plugin {}", broken.dir.display()),
+                Err(e) => {
+                    if let Some(e) = e {
+                        error!("Failed to load plugin {}: {}", broken.dir.display(), e);
+                    }
+                    self.broken_plugins.push(broken);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,258 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+use super::map::normalize_map_name;
+use super::Server;
+use crate::config::ConfigMapRotation;
+
+/// Keeps track of when the next map change happens and which map it goes to.
+pub struct MapRotation {
+    enabled: bool,
+    voting: bool,
+    interval: Duration,
+    announce_before: Duration,
+    maps: Vec<String>,
+
+    /// Position of the current map in `maps`, None if it isn't in the rotation
+    index: Option<usize>,
+    next_change: Instant,
+    announced: bool,
+    /// Steam ID -> map
+    votes: HashMap<u64, String>,
+}
+
+impl MapRotation {
+    pub fn new(config: &ConfigMapRotation, current_map: &str) -> Self {
+        let interval = Duration::from_secs(config.interval);
+        let maps = config
+            .maps
+            .iter()
+            .filter_map(|m| {
+                normalize_map_name(m)
+                    .map_err(|e| error!("Skipping map in rotation: {}", e))
+                    .ok()
+            })
+            .collect::<Vec<_>>();
+        let index = normalize_map_name(current_map)
+            .ok()
+            .and_then(|current| maps.iter().position(|m| *m == current));
+        Self {
+            enabled: config.enabled && !maps.is_empty(),
+            voting: config.voting,
+            interval,
+            announce_before: Duration::from_secs(config.announce_before),
+            maps,
+
+            index,
+            next_change: Instant::now() + interval,
+            announced: false,
+            votes: HashMap::new(),
+        }
+    }
+
+    /// Registers a vote for the next map. Returns the full map name on success.
+    pub fn vote(&mut self, steam_id: u64, map: &str) -> Result<String, String> {
+        if !self.enabled || !self.voting {
+            return Err(String::from("Map voting is disabled"));
+        }
+        let map = normalize_map_name(map)?;
+        if !self.maps.contains(&map) {
+            return Err(format!("{} is not in the map rotation", map));
+        }
+        self.votes.insert(steam_id, map.clone());
+        Ok(map)
+    }
+
+    /// The map with the most votes wins, with ties going to whichever map comes first in
+    /// the rotation. Without any votes we just go to the next map in the list, or the first one
+    /// if the current map isn't in the rotation.
+    fn next_map(&self, connected: impl Fn(u64) -> bool) -> String {
+        let mut tally = HashMap::<&str, usize>::new();
+        for (steam_id, map) in &self.votes {
+            if connected(*steam_id) {
+                *tally.entry(map.as_str()).or_default() += 1;
+            }
+        }
+
+        self.maps
+            .iter()
+            .filter_map(|map| tally.get(map.as_str()).map(|count| (map, *count)))
+            .fold(
+                None,
+                |best: Option<(&String, usize)>, (map, count)| match best {
+                    Some((_, best_count)) if best_count >= count => best,
+                    _ => Some((map, count)),
+                },
+            )
+            .map(|(map, _)| map.clone())
+            .unwrap_or_else(|| {
+                let next = self.index.map_or(0, |index| (index + 1) % self.maps.len());
+                self.maps[next].clone()
+            })
+    }
+
+    /// Starts a new rotation period on `map`, which also happens when the map is changed manually.
+    pub(super) fn advance(&mut self, map: &str) {
+        self.index = self.maps.iter().position(|m| m == map);
+        self.next_change = Instant::now() + self.interval;
+        self.announced = false;
+        self.votes.clear();
+    }
+}
+
+impl Server {
+    /// Announces and performs scheduled map changes.
+    pub(super) async fn update_map_rotation(&mut self) {
+        if !self.rotation.enabled {
+            return;
+        }
+
+        let now = Instant::now();
+        let change_due = now >= self.rotation.next_change;
+        let announce_due = !self.rotation.announced
+            && now + self.rotation.announce_before >= self.rotation.next_change;
+        if !change_due && !announce_due {
+            return;
+        }
+
+        let next_map = self
+            .rotation
+            .next_map(|steam_id| self.clients.0.contains_key(&steam_id));
+        if change_due {
+            if let Err(e) = self.change_map(next_map).await {
+                error!("Map rotation failed: {}", e);
+            }
+        } else {
+            self.rotation.announced = true;
+            let remaining = self.rotation.next_change.saturating_duration_since(now);
+            self.broadcast_server_message(format!(
+                "Map changes to {} in {} seconds",
+                next_map,
+                remaining.as_secs()
+            ))
+            .await;
+        }
+    }
+
+    /// Skips straight to the next map in the rotation.
+    pub(super) async fn skip_map_rotation(&mut self) -> Result<String, String> {
+        if !self.rotation.enabled {
+            return Err(String::from("Map rotation is disabled"));
+        }
+        let next_map = self
+            .rotation
+            .next_map(|steam_id| self.clients.0.contains_key(&steam_id));
+        self.change_map(next_map).await
+    }
+
+    pub(super) async fn vote_map(&mut self, steam_id: u64, map: &str) -> Result<String, String> {
+        let Some(name) = self.clients.0.get(&steam_id).map(|c| c.user.name.clone()) else {
+            return Err(format!("Player {} is not connected", steam_id));
+        };
+        let map = self.rotation.vote(steam_id, map)?;
+        self.broadcast_server_message(format!("{} voted for {}", name, map))
+            .await;
+        Ok(map)
+    }
+
+    pub(super) fn map_rotation_status(&self) -> String {
+        if !self.rotation.enabled {
+            return String::from("Map rotation is disabled");
+        }
+
+        let next_map = self
+            .rotation
+            .next_map(|steam_id| self.clients.0.contains_key(&steam_id));
+        let remaining = self
+            .rotation
+            .next_change
+            .saturating_duration_since(Instant::now());
+        let mut output = format!(
+            "Next map: {} in {} seconds\nRotation:",
+            next_map,
+            remaining.as_secs()
+        );
+        for (i, map) in self.rotation.maps.iter().enumerate() {
+            let votes = self.rotation.votes.values().filter(|m| *m == map).count();
+            let marker = if Some(i) == self.rotation.index {
+                "*"
+            } else {
+                " "
+            };
+            let _ = write!(output, "\n {} {} ({} vote(s))", marker, map, votes);
+        }
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rotation(maps: &[&str], current_map: &str) -> MapRotation {
+        MapRotation::new(
+            &ConfigMapRotation {
+                enabled: true,
+                maps: maps.iter().map(|m| m.to_string()).collect(),
+                ..Default::default()
+            },
+            current_map,
+        )
+    }
+
+    fn full(map: &str) -> String {
+        normalize_map_name(map).unwrap()
+    }
+
+    #[test]
+    fn without_votes_goes_to_the_next_map() {
+        let first = rotation(&["a", "b", "c"], "a");
+        assert_eq!(first.next_map(|_| true), full("b"));
+
+        let last = rotation(&["a", "b", "c"], "c");
+        assert_eq!(last.next_map(|_| true), full("a"));
+    }
+
+    #[test]
+    fn starts_at_the_first_map_if_the_current_one_is_not_in_the_rotation() {
+        let rotation = rotation(&["a", "b", "c"], "other");
+        assert_eq!(rotation.next_map(|_| true), full("a"));
+    }
+
+    #[test]
+    fn most_votes_wins() {
+        let mut rotation = rotation(&["a", "b", "c"], "a");
+        rotation.vote(1, "c").unwrap();
+        rotation.vote(2, "c").unwrap();
+        rotation.vote(3, "b").unwrap();
+        assert_eq!(rotation.next_map(|_| true), full("c"));
+    }
+
+    #[test]
+    fn ties_go_to_the_map_that_comes_first() {
+        let mut rotation = rotation(&["a", "b", "c"], "a");
+        rotation.vote(1, "c").unwrap();
+        rotation.vote(2, "b").unwrap();
+        assert_eq!(rotation.next_map(|_| true), full("b"));
+    }
+
+    #[test]
+    fn votes_of_disconnected_players_do_not_count() {
+        let mut rotation = rotation(&["a", "b", "c"], "a");
+        rotation.vote(1, "c").unwrap();
+        rotation.vote(2, "c").unwrap();
+        rotation.vote(3, "a").unwrap();
+        assert_eq!(rotation.next_map(|steam_id| steam_id == 3), full("a"));
+        assert_eq!(rotation.next_map(|_| false), full("b"));
+    }
+
+    #[test]
+    fn advance_clears_votes() {
+        let mut rotation = rotation(&["a", "b", "c"], "a");
+        rotation.vote(1, "c").unwrap();
+        rotation.advance(&full("b"));
+        assert_eq!(rotation.next_map(|_| true), full("c"));
+        assert!(rotation.votes.is_empty());
+    }
+}
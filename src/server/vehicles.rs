@@ -0,0 +1,144 @@
+use std::time::{Duration, Instant};
+
+use ngmp_protocol_impl::server_launcher;
+use ngmp_protocol_impl::server_launcher::Packet;
+
+use super::Server;
+
+/// How long transforms from the owner are ignored after a teleport, giving them time to
+/// receive it
+const TELEPORT_GRACE_PERIOD: Duration = Duration::from_secs(1);
+
+impl Server {
+    /// Asks plugins whether a vehicle that was just spawned by its owner may stay.
+    pub(super) async fn allow_vehicle_spawn(&self, steam_id: u64, vehicle_id: u16) -> bool {
+        let Some(data) = self
+            .clients
+            .0
+            .get(&steam_id)
+            .and_then(|client| client.vehicles.get(&vehicle_id))
+            .map(|veh| veh.data_json.clone())
+        else {
+            return false;
+        };
+        self.plugins
+            .event_on_vehicle_spawn(steam_id, vehicle_id, &data)
+            .await
+    }
+
+    /// Undoes a vehicle spawn that was confirmed to the owner, but not yet sent to
+    /// anyone else.
+    pub(super) async fn cancel_vehicle_spawn(&mut self, steam_id: u64, vehicle_id: u16) {
+        let Some(client) = self.clients.0.get_mut(&steam_id) else {
+            return;
+        };
+        client.vehicles.remove(&vehicle_id);
+        if let Err(e) = client
+            .tcp_conn
+            .write_packet(&Packet::VehicleDelete(
+                server_launcher::gameplay::VehicleDeletePacket {
+                    player_id: steam_id,
+                    vehicle_id,
+                },
+            ))
+            .await
+        {
+            error!("{}", e);
+        }
+    }
+
+    /// Removes a vehicle, tells every client except `exclude_id` to delete it and lets plugins
+    /// know through `onVehicleDelete`. Returns false if the vehicle didn't exist.
+    ///
+    /// Unlike spawns, deletes can't be cancelled by plugins. When the owner deletes a vehicle
+    /// it is already gone on their end, and the protocol has no way to give it back to them.
+    pub(super) async fn delete_vehicle(
+        &mut self,
+        steam_id: u64,
+        vehicle_id: u16,
+        exclude_id: Option<u64>,
+    ) -> bool {
+        let Some(client) = self.clients.0.get_mut(&steam_id) else {
+            return false;
+        };
+        if client.vehicles.remove(&vehicle_id).is_none() {
+            return false;
+        }
+
+        trace!("deleting vehicle ({steam_id}, {vehicle_id})");
+        self.clients
+            .tcp_broadcast_packet(
+                Packet::VehicleDelete(server_launcher::gameplay::VehicleDeletePacket {
+                    player_id: steam_id,
+                    vehicle_id,
+                }),
+                exclude_id,
+            )
+            .await;
+        self.plugins
+            .event_on_vehicle_delete(steam_id, vehicle_id)
+            .await;
+        true
+    }
+
+    /// Moves a vehicle to a new position, stopping it in place.
+    /// The owner gets the new transform over TCP, so it can't get lost, everyone else over UDP.
+    pub(super) async fn teleport_vehicle(
+        &mut self,
+        steam_id: u64,
+        vehicle_id: u16,
+        pos: [f32; 3],
+        rot: Option<[f32; 4]>,
+    ) -> bool {
+        let Some(client) = self.clients.0.get_mut(&steam_id) else {
+            return false;
+        };
+        let Some(veh) = client.vehicles.get_mut(&vehicle_id) else {
+            return false;
+        };
+
+        // Until the owner has the teleport, the transforms they send still have the old position
+        veh.teleported_until = Some(Instant::now() + TELEPORT_GRACE_PERIOD);
+
+        let transform = &mut veh.latest_transform;
+        transform.pos = pos;
+        if let Some(rot) = rot {
+            transform.rot = rot;
+        }
+        transform.vel = [0.0; 3];
+        transform.rvel = [0.0; 3];
+
+        let transform =
+            serde_json::to_string(transform).expect("Somehow failed to serialize to json!");
+        let packet = |transform: String| {
+            Packet::VehicleTransform(server_launcher::gameplay::VehicleTransformPacket {
+                player_id: steam_id,
+                vehicle_id,
+                transform,
+            })
+        };
+        if client.map_load.is_none() {
+            if let Err(e) = client
+                .tcp_conn
+                .write_packet(&packet(transform.clone()))
+                .await
+            {
+                error!("{}", e);
+            }
+        }
+
+        for (other_id, client) in self.clients.0.iter() {
+            if *other_id == steam_id || client.map_load.is_some() {
+                continue;
+            }
+            if let Err(e) = self
+                .udp
+                .udp_send_packet(client.udp_addr, packet(transform.clone()))
+                .await
+            {
+                error!("{}", e);
+            }
+        }
+        true
+    }
+}